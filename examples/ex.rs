@@ -1,5 +1,4 @@
-use interaction::InteractionBuilder;
-use std::io;
+use interaction::{InteractionBuilder, ReadlineError};
 
 fn main() {
     let history_file = "./.example_history";
@@ -18,7 +17,10 @@ fn main() {
             Ok(input) => {
                 println!("Input: {:?} {}", input, input.len());
             }
-            Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+            Err(ReadlineError::Interrupted) => {
+                continue;
+            }
+            Err(ReadlineError::Eof) => {
                 inter.save_history(history_file).unwrap();
                 break;
             }
@@ -0,0 +1,524 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::ops::Range;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::unicode;
+use crate::Completion;
+
+/// A candidate of completion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    /// The text which replaces the range of the line.
+    pub replacement: Vec<u8>,
+    /// The label which is listed. By default, it is the replacement.
+    pub display: Vec<u8>,
+    /// The description which is listed next to the label.
+    pub description: Option<Vec<u8>>,
+}
+
+impl Candidate {
+    /// Initialize a candidate of `replacement`.
+    pub fn new(replacement: &[u8]) -> Self {
+        Candidate {
+            replacement: replacement.to_vec(),
+            display: replacement.to_vec(),
+            description: None,
+        }
+    }
+
+    /// Set the label which is listed.
+    pub fn display(mut self, display: &[u8]) -> Self {
+        self.display = display.to_vec();
+        self
+    }
+
+    /// Set the description which is listed next to the label.
+    pub fn description(mut self, description: &[u8]) -> Self {
+        self.description = Some(description.to_vec());
+        self
+    }
+}
+
+impl From<Vec<u8>> for Candidate {
+    fn from(replacement: Vec<u8>) -> Self {
+        Candidate::new(&replacement)
+    }
+}
+
+impl From<&str> for Candidate {
+    fn from(replacement: &str) -> Self {
+        Candidate::new(replacement.as_bytes())
+    }
+}
+
+/// The behavior of the completion key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionMode {
+    /// Replace the text with each candidate in turn.
+    Cycle,
+    /// Insert the longest common prefix of the candidates, and list them on the second press
+    /// like bash.
+    List,
+    /// Select a candidate in the menu below the line with the arrow keys, Tab and Shift-Tab.
+    /// Enter accepts the candidate and Escape restores the line.
+    Menu,
+}
+
+/// The completer of a line.
+///
+/// A closure `FnMut(&[u8], usize) -> (Range<usize>, Vec<Candidate>)` is also a completer.
+///
+/// # Example
+/// ```no_run
+/// use interaction::{Candidate, InteractionBuilder};
+///
+/// let commands = vec![
+///     ("commit", "Record changes to the repository"),
+///     ("checkout", "Switch branches or restore working tree files"),
+///     ("cherry-pick", "Apply the changes introduced by some existing commits"),
+/// ];
+/// let inter = InteractionBuilder::new()
+///     .completer(move |line: &[u8], position: usize| {
+///         // Complete the word before the cursor.
+///         let start = line[..position]
+///             .iter()
+///             .rposition(|c| *c == b' ')
+///             .map_or(0, |i| i + 1);
+///         let candidates = commands
+///             .iter()
+///             .filter(|(c, _)| c.as_bytes().starts_with(&line[start..position]))
+///             .map(|(c, d)| Candidate::new(c.as_bytes()).description(d.as_bytes()))
+///             .collect();
+///         (start..position, candidates)
+///     })
+///     .build();
+/// ```
+pub trait Completer {
+    /// Return the candidates to complete `line` at the byte offset `position`, and the range of
+    /// `line` which a candidate replaces.
+    fn complete(&mut self, line: &[u8], position: usize) -> (Range<usize>, Vec<Candidate>);
+}
+
+impl<F: FnMut(&[u8], usize) -> (Range<usize>, Vec<Candidate>)> Completer for F {
+    fn complete(&mut self, line: &[u8], position: usize) -> (Range<usize>, Vec<Candidate>) {
+        self(line, position)
+    }
+}
+
+/// The completer of a [Completion](type.Completion.html), whose candidates replace the whole line.
+pub(crate) struct CompletionCompleter(pub(crate) Completion);
+
+impl Completer for CompletionCompleter {
+    fn complete(&mut self, line: &[u8], _position: usize) -> (Range<usize>, Vec<Candidate>) {
+        let mut completions = Vec::new();
+        (self.0)(&line.to_vec(), &mut completions);
+        (
+            0..line.len(),
+            completions.into_iter().map(Candidate::from).collect(),
+        )
+    }
+}
+
+/// Return the rows to list `candidates` within `col` columns. The labels are in columns, or in
+/// a column with the descriptions next to them if any candidate has a description.
+pub(crate) fn list(candidates: &[Candidate], col: usize) -> Vec<Vec<u8>> {
+    let layout = Layout::new(candidates, col);
+    // The candidates are sorted down the columns like `ls`.
+    let rows = layout.rows(candidates.len());
+    (0..rows)
+        .map(|r| {
+            let cells: Vec<Vec<u8>> = candidates
+                .iter()
+                .skip(r)
+                .step_by(rows)
+                .map(|c| layout.cell(c))
+                .collect();
+            let row = cells.join(&b"  "[..]);
+            unicode::truncate(String::from_utf8_lossy(&row).trim_end().as_bytes(), col)
+        })
+        .collect()
+}
+
+/// The layout of candidates in columns.
+pub(crate) struct Layout {
+    // The number of columns.
+    pub(crate) columns: usize,
+    // The width of the labels.
+    width: usize,
+    // The number of columns of the terminal.
+    col: usize,
+}
+
+impl Layout {
+    pub(crate) fn new(candidates: &[Candidate], col: usize) -> Self {
+        let width = candidates
+            .iter()
+            .map(|c| unicode::width(&c.display))
+            .max()
+            .unwrap_or(0);
+        // The descriptions are next to the labels in a column.
+        let columns = if candidates.iter().any(|c| c.description.is_some()) {
+            1
+        } else {
+            ((col + 2) / (width + 2)).max(1)
+        };
+        Layout {
+            columns,
+            width,
+            col,
+        }
+    }
+
+    /// Return the number of rows to lay out `len` candidates.
+    pub(crate) fn rows(&self, len: usize) -> usize {
+        match len {
+            0 => 0,
+            len => (len - 1) / self.columns + 1,
+        }
+    }
+
+    /// Return the cell of `candidate`, which is the padded label and the description within the
+    /// columns of the terminal.
+    fn cell(&self, candidate: &Candidate) -> Vec<u8> {
+        let mut cell = candidate.display.clone();
+        let width = unicode::width(&cell);
+        cell.resize(cell.len() + self.width.saturating_sub(width), b' ');
+        if let Some(description) = &candidate.description {
+            cell.extend(b"  -- ");
+            cell.extend(description);
+        }
+        unicode::truncate(&cell, self.col)
+    }
+
+    /// Return `height` rows of the menu from the row `top`. The candidates are sorted across the
+    /// rows, and the `selected` candidate is highlighted.
+    pub(crate) fn menu(
+        &self,
+        candidates: &[Candidate],
+        selected: usize,
+        top: usize,
+        height: usize,
+    ) -> Vec<Vec<u8>> {
+        let mut rows: Vec<Vec<u8>> = candidates
+            .chunks(self.columns)
+            .enumerate()
+            .skip(top)
+            .take(height)
+            .map(|(r, chunk)| {
+                let mut row = vec![];
+                for (c, candidate) in chunk.iter().enumerate() {
+                    if c > 0 {
+                        row.extend(b"  ");
+                    }
+                    if r * self.columns + c == selected {
+                        row.extend(b"\x1b[7m");
+                        row.extend(self.cell(candidate));
+                        row.extend(b"\x1b[0m");
+                    } else {
+                        row.extend(self.cell(candidate));
+                    }
+                }
+                row
+            })
+            .collect();
+        let total = self.rows(candidates.len());
+        if total > height {
+            let status = format!("rows {} to {} of {}", top + 1, top + rows.len(), total);
+            rows.push(unicode::truncate(status.as_bytes(), self.col));
+        }
+        rows
+    }
+}
+
+/// Return the longest common prefix of the replacements of `candidates`.
+pub(crate) fn common_prefix(candidates: &[Candidate]) -> Vec<u8> {
+    let first = match candidates.first() {
+        Some(c) => &c.replacement,
+        None => return vec![],
+    };
+    let len = candidates.iter().skip(1).fold(first.len(), |len, c| {
+        first[..len]
+            .iter()
+            .zip(c.replacement.iter())
+            .take_while(|(a, b)| a == b)
+            .count()
+    });
+    first[..unicode::floor_char_boundary(first, len)].to_vec()
+}
+
+/// The completer of file names, which completes the word under the cursor against the
+/// filesystem. `~` is expanded to the home directory, and `/` is appended to a directory.
+/// Spaces and other special chars are escaped with `\`, or quoted if the word starts with a
+/// quote.
+///
+/// It can be composed with another completer in a closure.
+///
+/// # Example
+/// ```no_run
+/// use interaction::{Candidate, Completer, FilenameCompleter, InteractionBuilder};
+///
+/// let mut files = FilenameCompleter::new().extensions(&["csv", "json"]);
+/// let inter = InteractionBuilder::new()
+///     .completer(move |line: &[u8], position: usize| {
+///         // Complete the command of the first word, and the file names after it.
+///         if line[..position].contains(&b' ') {
+///             files.complete(line, position)
+///         } else {
+///             let candidates = ["load", "save"]
+///                 .iter()
+///                 .filter(|c| c.as_bytes().starts_with(&line[..position]))
+///                 .map(|c| Candidate::from(*c))
+///                 .collect();
+///             (0..position, candidates)
+///         }
+///     })
+///     .build();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FilenameCompleter {
+    // If not empty, only the files which have one of them are completed.
+    extensions: Vec<String>,
+}
+
+impl FilenameCompleter {
+    /// Initialize a completer of file names.
+    pub fn new() -> Self {
+        FilenameCompleter::default()
+    }
+
+    /// Set the extensions of the files to complete, e.g. `&["rs", "toml"]`. Directories are
+    /// always completed.
+    pub fn extensions(mut self, extensions: &[&str]) -> Self {
+        self.extensions = extensions.iter().map(|e| e.to_string()).collect();
+        self
+    }
+
+    /// Return true if `path` has one of the extensions.
+    fn has_extension(&self, path: &Path) -> bool {
+        self.extensions.is_empty()
+            || match path.extension() {
+                Some(e) => self.extensions.iter().any(|x| OsStr::new(x) == e),
+                None => false,
+            }
+    }
+}
+
+impl Completer for FilenameCompleter {
+    fn complete(&mut self, line: &[u8], position: usize) -> (Range<usize>, Vec<Candidate>) {
+        let (start, quote, word) = word_at(&line[..position.min(line.len())]);
+        let (dir, prefix) = match word.iter().rposition(|c| *c == b'/') {
+            Some(i) => word.split_at(i + 1),
+            None => (&word[..0], &word[..]),
+        };
+        let path = match expand_home(dir) {
+            Some(path) if path.as_os_str().is_empty() => PathBuf::from("."),
+            Some(path) => path,
+            None => return (start..position, vec![]),
+        };
+        let entries = match fs::read_dir(&path) {
+            Ok(entries) => entries,
+            Err(_) => return (start..position, vec![]),
+        };
+        let mut candidates: Vec<(Vec<u8>, bool)> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().as_bytes().to_vec();
+                // Hidden files are completed only if the prefix starts with `.`.
+                if !name.starts_with(prefix) || (name.starts_with(b".") && prefix.is_empty()) {
+                    return None;
+                }
+                // Follow symbolic links to directories.
+                let is_dir = matches!(fs::metadata(entry.path()), Ok(m) if m.is_dir());
+                if is_dir || self.has_extension(&entry.path()) {
+                    Some((name, is_dir))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        candidates.sort();
+        let candidates = candidates
+            .into_iter()
+            .map(|(name, is_dir)| {
+                let mut text = [dir, &name[..]].concat();
+                let mut display = name;
+                if is_dir {
+                    text.push(b'/');
+                    display.push(b'/');
+                }
+                Candidate::new(&quote_word(&text, quote, !is_dir)).display(&display)
+            })
+            .collect();
+        (start..position, candidates)
+    }
+}
+
+/// Return the start of the last word in `line`, the quote which the word starts with, and the
+/// word without quotes and escapes.
+fn word_at(line: &[u8]) -> (usize, Option<u8>, Vec<u8>) {
+    let mut start = 0;
+    let mut first_quote = None;
+    let mut quote = None;
+    let mut word = vec![];
+    let mut chars = line.iter().enumerate();
+    while let Some((i, &c)) = chars.next() {
+        match (quote, c) {
+            (None, b' ') | (None, b'\t') => {
+                start = i + 1;
+                first_quote = None;
+                word.clear();
+            }
+            (None, b'\\') | (Some(b'"'), b'\\') => {
+                if let Some((_, &c)) = chars.next() {
+                    word.push(c);
+                }
+            }
+            (None, b'\'') | (None, b'"') => {
+                if i == start {
+                    first_quote = Some(c);
+                }
+                quote = Some(c);
+            }
+            (Some(q), c) if q == c => quote = None,
+            _ => word.push(c),
+        }
+    }
+    (start, first_quote, word)
+}
+
+/// Expand `~` at the start of `dir` to the home directory. Return `None` if there is no home.
+fn expand_home(dir: &[u8]) -> Option<PathBuf> {
+    match dir.strip_prefix(b"~") {
+        Some(rest) if rest.is_empty() || rest.starts_with(b"/") => {
+            let mut home = env::var_os("HOME")?.as_bytes().to_vec();
+            home.extend(rest);
+            Some(PathBuf::from(OsStr::from_bytes(&home)))
+        }
+        _ => Some(PathBuf::from(OsStr::from_bytes(dir))),
+    }
+}
+
+/// Quote `text` with `quote`, or escape the special chars with `\` if `quote` is `None`.
+/// If `close` is true, the quote is closed.
+fn quote_word(text: &[u8], quote: Option<u8>, close: bool) -> Vec<u8> {
+    let mut word = vec![];
+    match quote {
+        Some(q) => {
+            word.push(q);
+            for &c in text {
+                match (q, c) {
+                    (b'\'', b'\'') => word.extend(b"'\\''"),
+                    (b'"', b'"') | (b'"', b'\\') | (b'"', b'$') | (b'"', b'`') => {
+                        word.push(b'\\');
+                        word.push(c);
+                    }
+                    _ => word.push(c),
+                }
+            }
+            if close {
+                word.push(q);
+            }
+        }
+        None => {
+            for (i, &c) in text.iter().enumerate() {
+                // `~` at the start is expanded.
+                if (i > 0 || c != b'~') && b" \t\\'\"$`&;()|<>*?![]{}~#".contains(&c) {
+                    word.push(b'\\');
+                }
+                word.push(c);
+            }
+        }
+    }
+    word
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    #[test]
+    fn word() {
+        assert_eq!(word_at(b"cat foo"), (4, None, b"foo".to_vec()));
+        assert_eq!(word_at(b"cat my\\ fi"), (4, None, b"my fi".to_vec()));
+        assert_eq!(word_at(b"cat 'my fi"), (4, Some(b'\''), b"my fi".to_vec()));
+        assert_eq!(word_at(b"cat \"a\\\"b"), (4, Some(b'"'), b"a\"b".to_vec()));
+        assert_eq!(word_at(b"cat 'a b'c"), (4, Some(b'\''), b"a bc".to_vec()));
+        assert_eq!(word_at(b"cat a'b c"), (4, None, b"ab c".to_vec()));
+        assert_eq!(word_at(b"cat "), (4, None, vec![]));
+    }
+
+    #[test]
+    fn quote() {
+        assert_eq!(quote_word(b"my file", None, true), b"my\\ file");
+        assert_eq!(quote_word(b"~/a&b", None, true), b"~/a\\&b");
+        assert_eq!(quote_word(b"a~", None, true), b"a\\~");
+        assert_eq!(quote_word(b"my file", Some(b'\''), true), b"'my file'");
+        assert_eq!(quote_word(b"it's", Some(b'\''), true), b"'it'\\''s'");
+        assert_eq!(quote_word(b"a\"$b", Some(b'"'), true), b"\"a\\\"\\$b\"");
+        assert_eq!(quote_word(b"dir/", Some(b'"'), false), b"\"dir/");
+    }
+
+    #[test]
+    fn prefix() {
+        let prefix = |words: &[&str]| {
+            let candidates: Vec<Candidate> = words.iter().map(|w| Candidate::from(*w)).collect();
+            common_prefix(&candidates)
+        };
+        assert_eq!(prefix(&["foobar", "foobaz", "foo"]), b"foo");
+        assert_eq!(prefix(&["abc", "xyz"]), b"");
+        assert_eq!(prefix(&["abc"]), b"abc");
+        assert_eq!(prefix(&[]), b"");
+        // `é` and `è` share the first byte, which is not a char boundary.
+        assert_eq!(prefix(&["caté", "catè"]), b"cat");
+    }
+
+    #[test]
+    fn filename() {
+        let dir = env::temp_dir().join(format!("interaction-{}-files", process::id()));
+        fs::create_dir_all(dir.join("sub dir")).unwrap();
+        fs::write(dir.join("my file.txt"), b"").unwrap();
+        fs::write(dir.join("notes.csv"), b"").unwrap();
+        fs::write(dir.join(".hidden"), b"").unwrap();
+        let dir_name = dir.to_str().unwrap();
+        let complete = |line: String, extensions: &[&str]| {
+            let mut completer = FilenameCompleter::new().extensions(extensions);
+            let (range, candidates) = completer.complete(line.as_bytes(), line.len());
+            let replacements: Vec<String> = candidates
+                .into_iter()
+                .map(|c| String::from_utf8(c.replacement).unwrap())
+                .collect();
+            (range, replacements)
+        };
+
+        let line = format!("cat {}/", dir_name);
+        let (range, replacements) = complete(line.clone(), &[]);
+        assert_eq!(range, 4..line.len());
+        assert_eq!(
+            replacements,
+            [
+                format!("{}/my\\ file.txt", dir_name),
+                format!("{}/notes.csv", dir_name),
+                format!("{}/sub\\ dir/", dir_name),
+            ]
+        );
+        let (_, replacements) = complete(format!("cat '{}/s", dir_name), &[]);
+        assert_eq!(replacements, [format!("'{}/sub dir/", dir_name)]);
+        let (_, replacements) = complete(format!("cat \"{}/m", dir_name), &[]);
+        assert_eq!(replacements, [format!("\"{}/my file.txt\"", dir_name)]);
+        let (_, replacements) = complete(format!("cat {}/.", dir_name), &[]);
+        assert_eq!(replacements, [format!("{}/.hidden", dir_name)]);
+        let (_, replacements) = complete(format!("cat {}/", dir_name), &["csv"]);
+        assert_eq!(
+            replacements,
+            [
+                format!("{}/notes.csv", dir_name),
+                format!("{}/sub\\ dir/", dir_name),
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
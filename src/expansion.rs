@@ -0,0 +1,213 @@
+use crate::history::History;
+
+/// Expand the references to the history in `line` like bash, e.g. `!!`, `!$`, `!42`, `!git` and
+/// `^old^new`. Return `None` if `line` has no reference, or the message if it fails.
+pub(crate) fn expand(history: &History, line: &[u8]) -> Result<Option<Vec<u8>>, String> {
+    if line.starts_with(b"^") {
+        return substitute(history, line).map(Some);
+    }
+    let mut expanded = vec![];
+    let mut changed = false;
+    // The references in single quotes are not expanded.
+    let mut quoted = false;
+    let mut i = 0;
+    while i < line.len() {
+        match line[i] {
+            b'\'' => quoted = !quoted,
+            b'\\' if i + 1 < line.len() => {
+                expanded.extend(&line[i..i + 2]);
+                i += 2;
+                continue;
+            }
+            b'!' if !quoted => {
+                if let Some((text, len)) = reference(history, &line[i..])? {
+                    expanded.extend(text);
+                    changed = true;
+                    i += len;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        expanded.push(line[i]);
+        i += 1;
+    }
+    Ok(if changed { Some(expanded) } else { None })
+}
+
+/// Expand the reference at the start of `line`, which starts with `!`. Return the expanded text
+/// and the length of the reference, or `None` if it is not a reference.
+fn reference(history: &History, line: &[u8]) -> Result<Option<(Vec<u8>, usize)>, String> {
+    let digits = |s: &[u8]| s.iter().take_while(|c| c.is_ascii_digit()).count();
+    let number = |s: &[u8]| String::from_utf8_lossy(s).parse::<usize>().unwrap_or(0);
+    let len = history.len();
+    let (command, mut end) = match line.get(1) {
+        None | Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'=') | Some(b'(') | Some(b'"') => {
+            return Ok(None)
+        }
+        Some(b'!') => (len.checked_sub(1).and_then(|i| history.get(i)), 2),
+        // `!$`, `!^` and `!*` are the words of the previous command.
+        Some(b'$') | Some(b'^') | Some(b'*') => {
+            (len.checked_sub(1).and_then(|i| history.get(i)), 1)
+        }
+        Some(b'-') if digits(&line[2..]) > 0 => {
+            let n = digits(&line[2..]);
+            let i = len.checked_sub(number(&line[2..2 + n]));
+            (i.and_then(|i| history.get(i)), 2 + n)
+        }
+        Some(c) if c.is_ascii_digit() => {
+            let n = digits(&line[1..]);
+            let i = number(&line[1..1 + n]).checked_sub(1);
+            (i.and_then(|i| history.get(i)), 1 + n)
+        }
+        Some(b'?') => {
+            let query = &line[2..];
+            let n = query.iter().position(|c| *c == b'?').unwrap_or(query.len());
+            let query = &query[..n];
+            let entry = history.search(query).last().map(|(_, e)| e);
+            (entry, (2 + n + 1).min(line.len()))
+        }
+        Some(_) => {
+            // The prefix ends at a word designator or a metacharacter of the shell.
+            let n = line[1..]
+                .iter()
+                .position(|c| c.is_ascii_whitespace() || b":;&|()<>\"'`".contains(c))
+                .unwrap_or(line.len() - 1);
+            if n == 0 {
+                return Ok(None);
+            }
+            let prefix = &line[1..1 + n];
+            let entry = history.iter().rev().find(|e| e.command.starts_with(prefix));
+            (entry, 1 + n)
+        }
+    };
+    let command = match command {
+        Some(entry) => &entry.command,
+        None => {
+            let event = String::from_utf8_lossy(&line[..end]);
+            return Err(format!("{}: event not found", event));
+        }
+    };
+    // The word designator, e.g. `:$` of `!!:$`.
+    let designator = match line.get(end) {
+        Some(c @ b'$') | Some(c @ b'^') | Some(c @ b'*') if end == 1 => {
+            end += 1;
+            Some(vec![*c])
+        }
+        Some(b':') => match line.get(end + 1) {
+            Some(c @ b'$') | Some(c @ b'^') | Some(c @ b'*') => {
+                end += 2;
+                Some(vec![*c])
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let n = digits(&line[end + 1..]);
+                end += 1 + n;
+                Some(line[end - n..end].to_vec())
+            }
+            _ => None,
+        },
+        _ => None,
+    };
+    let designator = match designator {
+        Some(designator) => designator,
+        None => return Ok(Some((command.clone(), end))),
+    };
+    let words: Vec<&[u8]> = command
+        .split(|c| c.is_ascii_whitespace())
+        .filter(|w| !w.is_empty())
+        .collect();
+    let text = match &designator[..] {
+        b"$" => words.last().map(|w| w.to_vec()),
+        b"^" => words.get(1).map(|w| w.to_vec()),
+        b"*" => Some(words.get(1..).unwrap_or(&[]).join(&b' ')),
+        n => words.get(number(n)).map(|w| w.to_vec()),
+    };
+    match text {
+        Some(text) => Ok(Some((text, end))),
+        None => {
+            let reference = String::from_utf8_lossy(&line[..end]);
+            Err(format!("{}: bad word specifier", reference))
+        }
+    }
+}
+
+/// Replace `old` with `new` in the previous command by `^old^new^`.
+fn substitute(history: &History, line: &[u8]) -> Result<Vec<u8>, String> {
+    let mut parts = line[1..].splitn(3, |c| *c == b'^');
+    let old = parts.next().unwrap_or(b"");
+    let new = parts.next().unwrap_or(b"");
+    let rest = parts.next().unwrap_or(b"");
+    let failed = || format!("{}: substitution failed", String::from_utf8_lossy(line));
+    let command = match history.len().checked_sub(1).and_then(|i| history.get(i)) {
+        Some(entry) => &entry.command,
+        None => return Err(failed()),
+    };
+    let offset = match command.windows(old.len().max(1)).position(|w| w == old) {
+        Some(offset) => offset,
+        None => return Err(failed()),
+    };
+    let mut expanded = command[..offset].to_vec();
+    expanded.extend(new);
+    expanded.extend(&command[offset + old.len()..]);
+    expanded.extend(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> History {
+        let mut history = History::new(10);
+        history.append(b"git commit -m msg".to_vec());
+        history.append(b"make test".to_vec());
+        history.append(b"ls -l /tmp".to_vec());
+        history
+    }
+
+    fn expanded(line: &str) -> Option<String> {
+        expand(&history(), line.as_bytes())
+            .unwrap()
+            .map(|line| String::from_utf8(line).unwrap())
+    }
+
+    #[test]
+    fn previous_command() {
+        assert_eq!(expanded("sudo !!").as_deref(), Some("sudo ls -l /tmp"));
+        assert_eq!(expanded("cd !$").as_deref(), Some("cd /tmp"));
+        assert_eq!(expanded("echo !^").as_deref(), Some("echo -l"));
+        assert_eq!(expanded("echo !!:1").as_deref(), Some("echo -l"));
+    }
+
+    #[test]
+    fn event() {
+        assert_eq!(expanded("!-2").as_deref(), Some("make test"));
+        assert_eq!(expanded("!1").as_deref(), Some("git commit -m msg"));
+        assert_eq!(expanded("!?comm?").as_deref(), Some("git commit -m msg"));
+        assert_eq!(expanded("!ma;ls").as_deref(), Some("make test;ls"));
+        assert_eq!(expanded("echo (!ma)").as_deref(), Some("echo (make test)"));
+        assert_eq!(
+            expand(&history(), b"!nothing"),
+            Err("!nothing: event not found".to_string())
+        );
+    }
+
+    #[test]
+    fn substitution() {
+        assert_eq!(expanded("^tmp^var").as_deref(), Some("ls -l /var"));
+        assert_eq!(
+            expanded("^-l^-a^ | less").as_deref(),
+            Some("ls -a /tmp | less")
+        );
+        assert!(expand(&history(), b"^nothing^x").is_err());
+    }
+
+    #[test]
+    fn not_expanded() {
+        assert_eq!(expanded("echo 'hi!!'"), None);
+        assert_eq!(expanded("echo hi\\!!"), None);
+        assert_eq!(expanded("echo \"hi!\""), None);
+        assert_eq!(expanded("echo hi! there"), None);
+        assert_eq!(expanded("[ ! -f a ]"), None);
+    }
+}
@@ -0,0 +1,839 @@
+use std::collections::{vec_deque, BTreeMap, HashSet, VecDeque};
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// The first line of a history file in the escaped format.
+const HEADER: &[u8] = b"#interaction-history-v2";
+
+// The callback to reject commands.
+type Ignore = Box<dyn Fn(&[u8]) -> bool + Send>;
+
+/// The policy for a command which is already in the history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Duplicates {
+    /// Append all commands.
+    Keep,
+    /// Ignore a command which is the same as the last command.
+    IgnoreConsecutive,
+    /// Erase all older commands which are the same as a new command. They are erased from the
+    /// file by [append_to](struct.History.html#method.append_to) too.
+    EraseAll,
+}
+
+/// A command in the history with the metadata.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Entry {
+    /// The command.
+    pub command: Vec<u8>,
+    /// The time when the command was run. `None` if it is unknown, e.g. loaded from an old file.
+    pub timestamp: Option<SystemTime>,
+    /// The working directory where the command was run.
+    pub cwd: Option<PathBuf>,
+    /// The exit status of the command.
+    pub exit_status: Option<i32>,
+    /// The metadata defined by the user.
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl Entry {
+    /// Initialize an entry of `command` which is run now.
+    pub fn new(command: Vec<u8>) -> Self {
+        Entry {
+            command,
+            timestamp: Some(SystemTime::now()),
+            ..Default::default()
+        }
+    }
+
+    /// Return true if the entry has any metadata to be saved.
+    fn has_metadata(&self) -> bool {
+        self.timestamp.is_some()
+            || self.cwd.is_some()
+            || self.exit_status.is_some()
+            || !self.metadata.is_empty()
+    }
+
+    /// Return true if `other` is the same entry as it is read from a file, which keeps the
+    /// timestamp in seconds.
+    fn is_same(&self, other: &Entry) -> bool {
+        let secs = |e: &Entry| {
+            e.timestamp
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+        };
+        self.command == other.command && self.cwd == other.cwd && secs(self) == secs(other)
+    }
+
+    /// Return the comment line of the metadata, e.g. `#1700000000\tcwd=/home\tstatus=0`.
+    fn metadata_line(&self) -> Vec<u8> {
+        let mut line = b"#".to_vec();
+        if let Some(timestamp) = self.timestamp {
+            let secs = timestamp
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            line.extend(secs.to_string().as_bytes());
+        }
+        let mut field = |key: &[u8], value: &[u8]| {
+            line.push(b'\t');
+            // `=` in the key is escaped because it separates the key and the value.
+            for c in escape(key) {
+                if c == b'=' {
+                    line.push(b'\\');
+                }
+                line.push(c);
+            }
+            line.push(b'=');
+            line.extend(escape(value));
+        };
+        if let Some(cwd) = &self.cwd {
+            field(b"cwd", cwd.as_os_str().as_bytes());
+        }
+        if let Some(status) = self.exit_status {
+            field(b"status", status.to_string().as_bytes());
+        }
+        for (key, value) in self.metadata.iter() {
+            field(key.as_bytes(), value.as_bytes());
+        }
+        line
+    }
+
+    /// Parse the comment line of the metadata. Return `None` if `line` is another comment.
+    fn parse_metadata(line: &[u8]) -> Option<Self> {
+        let mut fields = line.strip_prefix(b"#")?.split(|c| *c == b'\t');
+        let secs = fields.next()?;
+        if !secs.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        let mut entry = Entry {
+            timestamp: String::from_utf8_lossy(secs)
+                .parse()
+                .ok()
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+            ..Default::default()
+        };
+        for field in fields {
+            // Split the field at the first `=` which is not escaped.
+            let mut escaped = false;
+            let n = field.iter().position(|c| {
+                let found = !escaped && *c == b'=';
+                escaped = !escaped && *c == b'\\';
+                found
+            });
+            let (key, value) = match n {
+                Some(n) => (&field[..n], &field[n + 1..]),
+                None => (field, &b""[..]),
+            };
+            let key = String::from_utf8_lossy(&unescape(key)).into_owned();
+            let value = unescape(value);
+            match key.as_str() {
+                "cwd" => entry.cwd = Some(PathBuf::from(OsStr::from_bytes(&value))),
+                "status" => entry.exit_status = String::from_utf8_lossy(&value).parse().ok(),
+                _ => {
+                    entry
+                        .metadata
+                        .insert(key, String::from_utf8_lossy(&value).into_owned());
+                }
+            }
+        }
+        Some(entry)
+    }
+}
+
+/// The struct is to management the history of command line.
+pub struct History {
+    pub(crate) commands: VecDeque<Entry>,
+    pub(crate) position: usize,
+    // if limit is 0, history is unlimited.
+    limit: usize,
+    duplicates: Duplicates,
+    // If true, a command which starts with a space is ignored.
+    ignore_space: bool,
+    // A command is ignored if it returns true.
+    ignore: Option<Ignore>,
+    // The number of the newest entries which are not saved in a file.
+    unsaved: usize,
+    // The file which each entry is appended to.
+    file: Option<PathBuf>,
+}
+
+impl History {
+    /// Initialize history. `limit` is the maximum size of history. If limit is zero, unlimited.
+    pub fn new(limit: usize) -> Self {
+        History {
+            commands: VecDeque::new(),
+            position: 0,
+            limit,
+            duplicates: Duplicates::Keep,
+            ignore_space: false,
+            ignore: None,
+            unsaved: 0,
+            file: None,
+        }
+    }
+
+    /// Set the maximum size of history. If limit is zero, unlimited.
+    /// The oldest commands over the limit are removed.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        if limit > 0 && self.commands.len() > limit {
+            self.commands.drain(..self.commands.len() - limit);
+            self.unsaved = self.unsaved.min(limit);
+        }
+        self.position = self.commands.len();
+    }
+
+    /// Set the policy for duplicated commands. By default, all commands are appended.
+    pub fn set_duplicates(&mut self, duplicates: Duplicates) {
+        self.duplicates = duplicates;
+    }
+
+    /// If `ignore_space` is true, a command which starts with a space is not appended.
+    pub fn set_ignore_space(&mut self, ignore_space: bool) {
+        self.ignore_space = ignore_space;
+    }
+
+    /// Set a callback to reject commands. A command is not appended if `ignore` returns true,
+    /// e.g. the command contains a password.
+    pub fn set_ignore<F: Fn(&[u8]) -> bool + Send + 'static>(&mut self, ignore: F) {
+        self.ignore = Some(Box::new(ignore));
+    }
+
+    /// Return true if `history` should not be appended by the policies.
+    fn is_ignored(&self, history: &[u8]) -> bool {
+        if self.ignore_space && history.starts_with(b" ") {
+            return true;
+        }
+        if self.duplicates == Duplicates::IgnoreConsecutive
+            && self.commands.back().map(|e| &e.command[..]) == Some(history)
+        {
+            return true;
+        }
+        match &self.ignore {
+            Some(ignore) => ignore(history),
+            None => false,
+        }
+    }
+
+    /// Return a next command from the current line.
+    pub(crate) fn next(&mut self) -> Option<&Vec<u8>> {
+        if self.commands.is_empty() || self.position == self.commands.len() {
+            None
+        } else {
+            self.position += 1;
+            self.commands.get(self.position).map(|e| &e.command)
+        }
+    }
+
+    /// Return a previous command from the current line.
+    pub(crate) fn prev(&mut self) -> Option<&Vec<u8>> {
+        if self.commands.is_empty() || self.position == 0 {
+            None
+        } else {
+            self.position -= 1;
+            self.commands.get(self.position).map(|e| &e.command)
+        }
+    }
+
+    /// Return a previous command which starts with `prefix` from the current line.
+    pub(crate) fn prev_with_prefix(&mut self, prefix: &[u8]) -> Option<&Vec<u8>> {
+        let position = (0..self.position)
+            .rev()
+            .find(|&i| self.commands[i].command.starts_with(prefix))?;
+        self.position = position;
+        self.commands.get(self.position).map(|e| &e.command)
+    }
+
+    /// Return a next command which starts with `prefix` from the current line.
+    /// If there is no such command, move to the end of the history.
+    pub(crate) fn next_with_prefix(&mut self, prefix: &[u8]) -> Option<&Vec<u8>> {
+        let start = (self.position + 1).min(self.commands.len());
+        self.position = (start..self.commands.len())
+            .find(|&i| self.commands[i].command.starts_with(prefix))
+            .unwrap_or(self.commands.len());
+        self.commands.get(self.position).map(|e| &e.command)
+    }
+
+    /// Search `query` in the commands from `start` to the older one if `reverse` is true, or
+    /// to the newer one. Return the index of the matched command and the offset of `query`.
+    pub(crate) fn search_from(
+        &self,
+        query: &[u8],
+        start: usize,
+        reverse: bool,
+    ) -> Option<(usize, usize)> {
+        let matched = |i: usize| {
+            self.commands
+                .get(i)
+                .and_then(|e| find(&e.command, query))
+                .map(|offset| (i, offset))
+        };
+        if reverse {
+            (0..=start).rev().find_map(matched)
+        } else {
+            (start..self.commands.len()).find_map(matched)
+        }
+    }
+
+    fn _append(&mut self, entry: Entry) {
+        if self.limit > 0 && self.commands.len() == self.limit {
+            self.commands.pop_front();
+            self.unsaved = self.unsaved.min(self.commands.len());
+        }
+        self.commands.push_back(entry);
+    }
+
+    /// Append a new command which is run now. The command is ignored or erases older commands
+    /// by the policies.
+    pub fn append(&mut self, history: Vec<u8>) {
+        self.append_entry(Entry::new(history));
+    }
+
+    /// Append a new entry. The entry is ignored or erases older entries by the policies.
+    /// Return true if the entry is appended.
+    pub fn append_entry(&mut self, entry: Entry) -> bool {
+        let appended = !self.is_ignored(&entry.command);
+        if appended {
+            if self.duplicates == Duplicates::EraseAll {
+                // Erased entries which are not saved yet are not appended to the file.
+                let saved = self.commands.len() - self.unsaved;
+                let mut i = 0;
+                let mut erased = 0;
+                self.commands.retain(|e| {
+                    let keep = e.command != entry.command;
+                    if !keep && i >= saved {
+                        erased += 1;
+                    }
+                    i += 1;
+                    keep
+                });
+                self.unsaved -= erased;
+            }
+            self._append(entry);
+            self.unsaved += 1;
+        }
+        self.position = self.commands.len();
+        appended
+    }
+
+    /// Return the number of the entries.
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    /// Return true if there is no entry.
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Return the entry at `index`. The oldest entry is at 0.
+    pub fn get(&self, index: usize) -> Option<&Entry> {
+        self.commands.get(index)
+    }
+
+    /// Return an iterator over the entries from the oldest one.
+    pub fn iter(&self) -> vec_deque::Iter<'_, Entry> {
+        self.commands.iter()
+    }
+
+    /// Return an iterator over the indexes and the entries whose commands contain `query`.
+    pub fn search<'a>(&'a self, query: &'a [u8]) -> impl Iterator<Item = (usize, &'a Entry)> {
+        self.filter(move |e| find(&e.command, query).is_some())
+    }
+
+    /// Return an iterator over the indexes and the entries which match `predicate`.
+    pub fn filter<'a, F: FnMut(&Entry) -> bool + 'a>(
+        &'a self,
+        mut predicate: F,
+    ) -> impl Iterator<Item = (usize, &'a Entry)> {
+        self.commands
+            .iter()
+            .enumerate()
+            .filter(move |(_, e)| predicate(e))
+    }
+
+    /// Remove the entry at `index` and return it.
+    ///
+    /// If the file is set by [set_file](#method.set_file), the entry is removed from the file
+    /// too. Otherwise the file is updated by [save](#method.save).
+    pub fn remove(&mut self, index: usize) -> io::Result<Option<Entry>> {
+        let entry = match self.commands.remove(index) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        self.position = self.commands.len();
+        if index >= self.commands.len() + 1 - self.unsaved {
+            self.unsaved -= 1;
+        } else {
+            self.rewrite_file(|entries| {
+                if let Some(i) = entries.iter().rposition(|e| e.is_same(&entry)) {
+                    entries.remove(i);
+                }
+            })?;
+        }
+        Ok(Some(entry))
+    }
+
+    /// Remove all entries, like bash's `history -c`. The file set by
+    /// [set_file](#method.set_file) is not changed, so use [clear_file](#method.clear_file) to
+    /// remove the entries in it.
+    pub fn clear(&mut self) {
+        self.commands.clear();
+        self.unsaved = 0;
+        self.position = 0;
+    }
+
+    /// Remove all entries in the file set by [set_file](#method.set_file), including the
+    /// entries appended by other sessions.
+    pub fn clear_file(&mut self) -> io::Result<()> {
+        self.rewrite_file(Vec::clear)
+    }
+
+    /// Set the exit status of the last entry.
+    ///
+    /// If the entry is already written to the file set by [set_file](#method.set_file), it is
+    /// updated in the file too. Otherwise the status is saved by the next [save](#method.save).
+    pub fn set_exit_status(&mut self, status: i32) -> io::Result<()> {
+        match self.commands.len().checked_sub(1) {
+            Some(index) => self.update(index, |e| e.exit_status = Some(status)),
+            None => Ok(()),
+        }
+    }
+
+    /// Update the entry at `index` with `update`, e.g. to add the metadata. The same entry in
+    /// the file set by [set_file](#method.set_file) is updated too if it is already written.
+    pub fn update<F: FnOnce(&mut Entry)>(&mut self, index: usize, update: F) -> io::Result<()> {
+        let saved = self.commands.len() - self.unsaved;
+        let entry = match self.commands.get_mut(index) {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+        let old = entry.clone();
+        update(entry);
+        let new = entry.clone();
+        if index >= saved {
+            return Ok(());
+        }
+        self.rewrite_file(|entries| {
+            if let Some(entry) = entries.iter_mut().rev().find(|e| e.is_same(&old)) {
+                *entry = new;
+            }
+        })
+    }
+
+    /// Set the file which each entry is appended to. The entries in the file are merged by
+    /// [merge](#method.merge).
+    pub fn set_file<P: AsRef<Path>>(&mut self, file_path: P) -> io::Result<()> {
+        self.merge(&file_path)?;
+        self.file = Some(file_path.as_ref().to_path_buf());
+        Ok(())
+    }
+
+    /// Append the entries which are not saved yet to the file set by
+    /// [set_file](#method.set_file).
+    pub(crate) fn sync(&mut self) -> io::Result<()> {
+        match self.file.clone() {
+            Some(file) if self.unsaved > 0 => self.append_to(file),
+            _ => Ok(()),
+        }
+    }
+
+    /// Rewrite the entries in the file set by [set_file](#method.set_file) with `update`.
+    fn rewrite_file<F: FnOnce(&mut Vec<Entry>)>(&self, update: F) -> io::Result<()> {
+        let file_path = match &self.file {
+            Some(file_path) => file_path,
+            None => return Ok(()),
+        };
+        let mut file = match OpenOptions::new().read(true).append(true).open(file_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        lock(&file, libc::LOCK_EX)?;
+        let mut buffer = vec![0; 0];
+        file.read_to_end(&mut buffer)?;
+        let mut entries = parse_entries(&buffer);
+        update(&mut entries);
+        file.set_len(0)?;
+        write_entries(&mut file, entries.iter())
+    }
+
+    /// Load a history from the given `file_path`.
+    ///
+    /// Both of the escaped format written by [save](#method.save) and the old plain format,
+    /// which is a command per line, can be loaded.
+    pub fn load<P: AsRef<Path>>(&mut self, file_path: P) -> io::Result<()> {
+        let entries = match read_entries(file_path.as_ref()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            self._append(entry);
+        }
+        self.unsaved = 0;
+        if !self.commands.is_empty() {
+            self.position = self.commands.len();
+        }
+        Ok(())
+    }
+
+    /// Save the history to the given `file_path`.
+    ///
+    /// Newlines and backslashes in commands are escaped, so a command in multiple lines is
+    /// loaded as it is. The metadata of an entry is saved in a comment line before the command.
+    ///
+    /// If the file is set by [set_file](#method.set_file) and `file_path` is another file, the
+    /// entries which are not saved yet are still appended to the file set.
+    pub fn save<P: AsRef<Path>>(&mut self, file_path: P) -> io::Result<()> {
+        let file_path = file_path.as_ref();
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(file_path)?;
+        lock(&file, libc::LOCK_EX)?;
+        file.set_len(0)?;
+        write_entries(&mut file, self.commands.iter())?;
+        if self.is_file(file_path) {
+            self.unsaved = 0;
+        }
+        Ok(())
+    }
+
+    /// Append the entries which are not saved yet to the given `file_path`, like bash's
+    /// `history -a`. The file is trimmed to the limit of the history.
+    ///
+    /// The file is locked with `flock` while it is written, so several sessions can share it.
+    pub fn append_to<P: AsRef<Path>>(&mut self, file_path: P) -> io::Result<()> {
+        let file_path = file_path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(file_path)?;
+        lock(&file, libc::LOCK_EX)?;
+        let mut buffer = vec![0; 0];
+        file.read_to_end(&mut buffer)?;
+        let new = self.commands.range(self.commands.len() - self.unsaved..);
+        let mut entries = parse_entries(&buffer);
+        let erased = self.duplicates == Duplicates::EraseAll
+            && entries
+                .iter()
+                .any(|e| new.clone().any(|n| n.command == e.command));
+        if buffer.starts_with(HEADER)
+            && !erased
+            && (self.limit == 0 || entries.len() + new.len() <= self.limit)
+        {
+            for entry in new {
+                write_entry(&mut file, entry)?;
+            }
+        } else {
+            // Rewrite the file of the old format, over the limit or with duplicates to erase.
+            entries.extend(new.cloned());
+            let keep = unique(&entries, self.duplicates);
+            let entries: Vec<&Entry> = entries
+                .iter()
+                .zip(keep)
+                .filter(|(_, keep)| *keep)
+                .map(|(e, _)| e)
+                .collect();
+            let skip = match self.limit {
+                0 => 0,
+                limit => entries.len().saturating_sub(limit),
+            };
+            file.set_len(0)?;
+            write_entries(&mut file, entries.into_iter().skip(skip))?;
+        }
+        if self.is_file(file_path) {
+            self.unsaved = 0;
+        }
+        file.flush()
+    }
+
+    /// Return true if `file_path` is the file set by [set_file](#method.set_file) or no file
+    /// is set, so the entries written to it are saved.
+    fn is_file(&self, file_path: &Path) -> bool {
+        match &self.file {
+            Some(file) => file == file_path,
+            None => true,
+        }
+    }
+
+    /// Replace the entries with the entries in the given `file_path`, which may be appended by
+    /// other sessions, followed by the entries which are not saved yet. The duplicates are
+    /// removed by the policy of [set_duplicates](#method.set_duplicates).
+    pub fn merge<P: AsRef<Path>>(&mut self, file_path: P) -> io::Result<()> {
+        let entries = match read_entries(file_path.as_ref()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e),
+        };
+        let unsaved: Vec<Entry> = self
+            .commands
+            .drain(self.commands.len() - self.unsaved..)
+            .collect();
+        let saved = entries.len();
+        let entries: Vec<Entry> = entries.into_iter().chain(unsaved).collect();
+        let keep = unique(&entries, self.duplicates);
+        let mut count = 0;
+        self.commands.clear();
+        for (i, (entry, keep)) in entries.into_iter().zip(keep).enumerate() {
+            if keep {
+                count += usize::from(i >= saved);
+                self._append(entry);
+            }
+        }
+        self.unsaved = count.min(self.commands.len());
+        self.position = self.commands.len();
+        Ok(())
+    }
+}
+
+/// Return the offset of `query` in `command`.
+fn find(command: &[u8], query: &[u8]) -> Option<usize> {
+    if query.is_empty() {
+        return Some(0);
+    }
+    command.windows(query.len()).position(|w| w == query)
+}
+
+/// Return whether each of `entries` is kept by the policy of `duplicates`.
+fn unique(entries: &[Entry], duplicates: Duplicates) -> Vec<bool> {
+    match duplicates {
+        Duplicates::Keep => vec![true; entries.len()],
+        Duplicates::IgnoreConsecutive => (0..entries.len())
+            .map(|i| i == 0 || entries[i - 1].command != entries[i].command)
+            .collect(),
+        Duplicates::EraseAll => {
+            // The newest one of the same commands is kept.
+            let mut seen = HashSet::new();
+            let mut keep: Vec<bool> = entries
+                .iter()
+                .rev()
+                .map(|e| seen.insert(&e.command))
+                .collect();
+            keep.reverse();
+            keep
+        }
+    }
+}
+
+/// Lock `file` with `flock`. The lock is released when the file is closed.
+fn lock(file: &File, operation: libc::c_int) -> io::Result<()> {
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// Read the entries in `file_path` with a shared lock.
+fn read_entries(file_path: &Path) -> io::Result<Vec<Entry>> {
+    let mut file = File::open(file_path)?;
+    lock(&file, libc::LOCK_SH)?;
+    let mut buffer = vec![0; 0];
+    file.read_to_end(&mut buffer)?;
+    Ok(parse_entries(&buffer))
+}
+
+/// Parse the entries in the escaped format, or in the old plain format if there is no header.
+fn parse_entries(buffer: &[u8]) -> Vec<Entry> {
+    let mut entries = vec![];
+    let mut lines = buffer.split(|c| *c == b'\n').peekable();
+    let escaped = lines.peek() == Some(&HEADER);
+    if escaped {
+        lines.next();
+    }
+    let mut metadata = None;
+    for line in lines {
+        if line.is_empty() {
+            continue;
+        }
+        if !escaped {
+            entries.push(Entry {
+                command: line.to_vec(),
+                ..Default::default()
+            });
+        } else if line[0] == b'#' {
+            metadata = Entry::parse_metadata(line).or(metadata);
+        } else {
+            let mut entry = metadata.take().unwrap_or_default();
+            entry.command = unescape(line);
+            entries.push(entry);
+        }
+    }
+    entries
+}
+
+/// Write the header and `entries` in the escaped format.
+fn write_entries<'a, W: Write, I: Iterator<Item = &'a Entry>>(
+    writer: &mut W,
+    entries: I,
+) -> io::Result<()> {
+    writer.write_all(HEADER).and(writer.write_all(b"\n"))?;
+    for entry in entries {
+        write_entry(writer, entry)?;
+    }
+    writer.flush()
+}
+
+/// Write `entry` in the escaped format. The metadata is written in a comment line before it.
+fn write_entry<W: Write>(writer: &mut W, entry: &Entry) -> io::Result<()> {
+    if entry.has_metadata() {
+        writer
+            .write_all(&entry.metadata_line())
+            .and(writer.write_all(b"\n"))?;
+    }
+    writer
+        .write_all(&escape(&entry.command))
+        .and(writer.write_all(b"\n"))
+}
+
+/// Escape a command to write it in a line. A newline is `\n`, a tab is `\t`, a backslash is
+/// `\\`, and `#` at the start is `\#` because the line is a comment.
+fn escape(cmd: &[u8]) -> Vec<u8> {
+    let mut line = Vec::with_capacity(cmd.len());
+    if cmd.starts_with(b"#") {
+        line.push(b'\\');
+    }
+    for c in cmd.iter() {
+        match c {
+            b'\n' => line.extend(b"\\n"),
+            b'\t' => line.extend(b"\\t"),
+            b'\\' => line.extend(b"\\\\"),
+            _ => line.push(*c),
+        }
+    }
+    line
+}
+
+/// Unescape a line which is escaped by [escape](fn.escape.html).
+fn unescape(line: &[u8]) -> Vec<u8> {
+    let mut cmd = Vec::with_capacity(line.len());
+    let mut chars = line.iter();
+    while let Some(c) = chars.next() {
+        match (c, chars.as_slice().first()) {
+            (b'\\', Some(b'n')) => cmd.push(b'\n'),
+            (b'\\', Some(b't')) => cmd.push(b'\t'),
+            (b'\\', Some(c)) => cmd.push(*c),
+            _ => {
+                cmd.push(*c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    fn temp_file(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("interaction-{}-{}", process::id(), name))
+    }
+
+    #[test]
+    fn escape_round_trip() {
+        let commands: [&[u8]; 7] = [
+            b"echo a\nb",
+            b"echo a\\b",
+            b"echo\ta",
+            b"# comment",
+            b"echo \\n",
+            b"echo \\",
+            b"echo a # b",
+        ];
+        for command in commands.iter() {
+            let line = escape(command);
+            assert!(!line.contains(&b'\n') && !line.contains(&b'\t'));
+            assert_ne!(line.first(), Some(&b'#'));
+            assert_eq!(unescape(&line), command.to_vec());
+        }
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = temp_file("save");
+        let mut history = History::new(10);
+        let mut entry = Entry::new(b"echo a\n\tb\\".to_vec());
+        entry.cwd = Some(PathBuf::from("/tmp/a b"));
+        entry.exit_status = Some(1);
+        entry
+            .metadata
+            .insert("k=ey".to_string(), "v=1\t".to_string());
+        history.append_entry(entry);
+        history.append(b"#not a comment".to_vec());
+        history.save(&path).unwrap();
+
+        let mut loaded = History::new(10);
+        loaded.load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.len(), 2);
+        for (loaded, entry) in loaded.iter().zip(history.iter()) {
+            assert!(loaded.is_same(entry));
+            assert_eq!(loaded.exit_status, entry.exit_status);
+            assert_eq!(loaded.metadata, entry.metadata);
+        }
+    }
+
+    #[test]
+    fn set_limit_with_unsaved_entries() {
+        let path = temp_file("limit");
+        let mut history = History::new(10);
+        for command in ["a", "b", "c", "d"].iter() {
+            history.append(command.as_bytes().to_vec());
+        }
+        history.set_limit(2);
+        history.set_duplicates(Duplicates::EraseAll);
+        history.append(b"c".to_vec());
+        assert_eq!(history.remove(0).unwrap().unwrap().command, b"d");
+        history.append_to(&path).unwrap();
+        let mut loaded = History::new(10);
+        loaded.load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded.get(0).unwrap().command, b"c");
+    }
+
+    #[test]
+    fn save_to_another_file() {
+        let (path, backup) = (temp_file("shared"), temp_file("backup"));
+        let mut history = History::new(10);
+        history.set_file(&path).unwrap();
+        history.append(b"ls".to_vec());
+        history.save(&backup).unwrap();
+        history.append_to(&backup).unwrap();
+        history.sync().unwrap();
+        let mut loaded = History::new(10);
+        loaded.load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&backup).unwrap();
+        assert_eq!(loaded.len(), 1);
+    }
+
+    #[test]
+    fn load_plain_file() {
+        let path = temp_file("plain");
+        fs::write(&path, b"ls -l\n#echo\n\necho a\\nb\n").unwrap();
+        let mut history = History::new(10);
+        history.load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let commands: Vec<&[u8]> = history.iter().map(|e| &e.command[..]).collect();
+        assert_eq!(commands, [&b"ls -l"[..], b"#echo", b"echo a\\nb"]);
+        assert!(history.iter().all(|e| e.timestamp.is_none()));
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::keys::{Key, KeyEvent, Modifiers};
+use crate::unicode;
+
+/// A command of line editing which is bound to a key.
+#[derive(Clone)]
+pub enum Command {
+    /// Move the cursor start of line.
+    BeginningOfLine,
+    /// Move the cursor end of line.
+    EndOfLine,
+    /// Move the cursor backward 1 char.
+    BackwardChar,
+    /// Move the cursor forward 1 char.
+    ForwardChar,
+    /// Move the cursor start of the word.
+    BackwardWord,
+    /// Move the cursor end of the word.
+    ForwardWord,
+    /// Delete a char at the cursor.
+    DeleteChar,
+    /// Delete a char at the cursor. If the line is empty, finish the input.
+    DeleteCharOrEof,
+    /// Delete a char before the cursor.
+    BackwardDeleteChar,
+    /// Kill chars from the cursor to the end of line.
+    KillLine,
+    /// Kill chars from the start of line to the cursor.
+    UnixLineDiscard,
+    /// Kill the word before the cursor. Words are separated by whitespaces.
+    UnixWordRubout,
+    /// Kill chars from the cursor to the end of the word.
+    KillWord,
+    /// Kill chars from the start of the word to the cursor.
+    BackwardKillWord,
+    /// Insert the last killed text at the cursor.
+    Yank,
+    /// Replace the yanked text with the text killed before it. Only after `Yank` or `YankPop`.
+    YankPop,
+    /// Clear the screen.
+    ClearScreen,
+    /// Replace the line with the previous command in the history.
+    PreviousHistory,
+    /// Replace the line with the next command in the history.
+    NextHistory,
+    /// Replace the line with the previous command which starts with the text before the cursor.
+    HistorySearchBackward,
+    /// Replace the line with the next command which starts with the text before the cursor.
+    HistorySearchForward,
+    /// Search the history backward incrementally.
+    ReverseSearchHistory,
+    /// Search the history forward incrementally.
+    ForwardSearchHistory,
+    /// Abort the incremental search and restore the line.
+    Abort,
+    /// Complete the line.
+    Complete,
+    /// List the completions below the line.
+    PossibleCompletions,
+    /// Toggle the overwrite mode.
+    ToggleOverwrite,
+    /// Undo the last change of the line. Consecutive typed chars are undone at once.
+    Undo,
+    /// Redo the last undone change.
+    Redo,
+    /// Insert the typed char.
+    SelfInsert,
+    /// Finish the line.
+    AcceptLine,
+    /// Cancel the line.
+    Interrupt,
+    /// Do nothing.
+    Noop,
+    /// A command defined by the user.
+    Custom(Arc<dyn Fn(&mut Editor) + Send + Sync>),
+}
+
+impl Command {
+    /// Initialize a command defined by the user.
+    pub fn custom<F: Fn(&mut Editor) + Send + Sync + 'static>(f: F) -> Self {
+        Command::Custom(Arc::new(f))
+    }
+}
+
+/// The line in editing, which a [Command::Custom](enum.Command.html) can edit.
+pub struct Editor<'a> {
+    buffer: &'a mut Vec<u8>,
+    position: &'a mut usize,
+}
+
+impl<'a> Editor<'a> {
+    pub(crate) fn new(buffer: &'a mut Vec<u8>, position: &'a mut usize) -> Self {
+        Editor { buffer, position }
+    }
+
+    /// Return the line.
+    pub fn buffer(&self) -> &[u8] {
+        self.buffer
+    }
+
+    /// Return the byte offset of the cursor.
+    pub fn position(&self) -> usize {
+        *self.position
+    }
+
+    /// Move the cursor to the byte offset `position`.
+    pub fn set_position(&mut self, position: usize) {
+        *self.position = unicode::floor_char_boundary(self.buffer, position);
+    }
+
+    /// Insert `text` at the cursor and move the cursor after it.
+    pub fn insert(&mut self, text: &[u8]) {
+        let position = *self.position;
+        self.replace(position..position, text);
+    }
+
+    /// Replace the bytes of `range` with `text` and move the cursor after it.
+    pub fn replace(&mut self, range: Range<usize>, text: &[u8]) {
+        let end = unicode::floor_char_boundary(self.buffer, range.end);
+        let start = unicode::floor_char_boundary(self.buffer, range.start.min(end));
+        let text = String::from_utf8_lossy(text);
+        self.buffer.splice(start..end, text.bytes());
+        *self.position = start + text.len();
+    }
+
+    /// Replace the line with `line` and move the cursor end of line.
+    pub fn set_buffer(&mut self, line: &[u8]) {
+        let end = self.buffer.len();
+        self.replace(0..end, line);
+    }
+}
+
+/// The result to look up keys in a keymap.
+pub(crate) enum Binding {
+    /// The keys are bound to the command.
+    Command(Command),
+    /// The keys are a prefix of bound keys, e.g. Ctrl-X of Ctrl-X Ctrl-U.
+    Prefix,
+    /// The keys are not bound.
+    None,
+}
+
+/// The table of key bindings.
+pub(crate) struct Keymap {
+    bindings: HashMap<Vec<KeyEvent>, Command>,
+}
+
+impl Keymap {
+    pub(crate) fn bind(&mut self, keys: &[KeyEvent], command: Command) {
+        self.bindings.insert(keys.to_vec(), command);
+    }
+
+    pub(crate) fn unbind(&mut self, keys: &[KeyEvent]) {
+        self.bindings.remove(keys);
+    }
+
+    /// Return the command bound to `key`. A char without modifiers is inserted by default.
+    pub(crate) fn get(&self, key: &KeyEvent) -> Option<Command> {
+        match self.lookup(&[*key]) {
+            Binding::Command(command) => Some(command),
+            _ => None,
+        }
+    }
+
+    /// Look up the sequence of `keys`.
+    pub(crate) fn lookup(&self, keys: &[KeyEvent]) -> Binding {
+        if let Some(command) = self.bindings.get(keys) {
+            return Binding::Command(command.clone());
+        }
+        if self
+            .bindings
+            .keys()
+            .any(|k| k.len() > keys.len() && k.starts_with(keys))
+        {
+            return Binding::Prefix;
+        }
+        match keys {
+            [KeyEvent {
+                key: Key::Char(_),
+                modifiers: Modifiers::NONE,
+            }] => Binding::Command(Command::SelfInsert),
+            _ => Binding::None,
+        }
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Keymap {
+            bindings: HashMap::new(),
+        };
+        let bindings = [
+            (KeyEvent::ctrl('a'), Command::BeginningOfLine),
+            (Key::Home.into(), Command::BeginningOfLine),
+            (KeyEvent::ctrl('b'), Command::BackwardChar),
+            (Key::Left.into(), Command::BackwardChar),
+            (KeyEvent::ctrl('c'), Command::Interrupt),
+            (KeyEvent::ctrl('d'), Command::DeleteCharOrEof),
+            (Key::Delete.into(), Command::DeleteChar),
+            (KeyEvent::ctrl('e'), Command::EndOfLine),
+            (Key::End.into(), Command::EndOfLine),
+            (KeyEvent::ctrl('f'), Command::ForwardChar),
+            (Key::Right.into(), Command::ForwardChar),
+            (KeyEvent::alt('b'), Command::BackwardWord),
+            (
+                KeyEvent::new(Key::Left, Modifiers::CTRL),
+                Command::BackwardWord,
+            ),
+            (KeyEvent::alt('f'), Command::ForwardWord),
+            (
+                KeyEvent::new(Key::Right, Modifiers::CTRL),
+                Command::ForwardWord,
+            ),
+            (KeyEvent::ctrl('h'), Command::BackwardDeleteChar),
+            (Key::Backspace.into(), Command::BackwardDeleteChar),
+            (KeyEvent::ctrl('j'), Command::AcceptLine),
+            (Key::Enter.into(), Command::AcceptLine),
+            (KeyEvent::ctrl('k'), Command::KillLine),
+            (KeyEvent::ctrl('u'), Command::UnixLineDiscard),
+            (KeyEvent::ctrl('w'), Command::UnixWordRubout),
+            (KeyEvent::alt('d'), Command::KillWord),
+            (
+                KeyEvent::new(Key::Backspace, Modifiers::ALT),
+                Command::BackwardKillWord,
+            ),
+            (
+                KeyEvent::new(Key::Char('h'), Modifiers::CTRL | Modifiers::ALT),
+                Command::BackwardKillWord,
+            ),
+            (KeyEvent::ctrl('y'), Command::Yank),
+            (KeyEvent::alt('y'), Command::YankPop),
+            (KeyEvent::ctrl('l'), Command::ClearScreen),
+            (Key::Up.into(), Command::PreviousHistory),
+            (Key::Down.into(), Command::NextHistory),
+            (Key::PageUp.into(), Command::HistorySearchBackward),
+            (Key::PageDown.into(), Command::HistorySearchForward),
+            (KeyEvent::ctrl('r'), Command::ReverseSearchHistory),
+            (KeyEvent::ctrl('s'), Command::ForwardSearchHistory),
+            (KeyEvent::ctrl('g'), Command::Abort),
+            (Key::Tab.into(), Command::Complete),
+            (KeyEvent::alt('?'), Command::PossibleCompletions),
+            (KeyEvent::alt('='), Command::PossibleCompletions),
+            (Key::Insert.into(), Command::ToggleOverwrite),
+            (KeyEvent::ctrl('_'), Command::Undo),
+            (KeyEvent::alt('_'), Command::Redo),
+        ];
+        for (key, command) in bindings.iter() {
+            keymap.bind(&[*key], command.clone());
+        }
+        keymap.bind(&[KeyEvent::ctrl('x'), KeyEvent::ctrl('u')], Command::Undo);
+        keymap
+    }
+}
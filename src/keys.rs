@@ -0,0 +1,354 @@
+use std::io;
+use std::ops::BitOr;
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+use crate::unicode;
+
+const ESC: u8 = 0x1b;
+const BACKSPACE: u8 = 0x7f;
+
+/// A key on the keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Key {
+    /// A char. A control char is `Char` with [Modifiers::CTRL](struct.Modifiers.html), e.g.
+    /// Ctrl-A is `Char('a')`.
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Esc,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    /// A function key, e.g. `F(1)` is F1.
+    F(u8),
+    /// An escape sequence which is not supported.
+    Unknown,
+}
+
+/// Modifier keys pressed with a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers(0);
+    pub const SHIFT: Modifiers = Modifiers(1);
+    /// Alt, or Meta.
+    pub const ALT: Modifiers = Modifiers(2);
+    pub const CTRL: Modifiers = Modifiers(4);
+
+    /// Return true if all modifiers of `other` are pressed.
+    pub fn contains(self, other: Modifiers) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Return the modifiers of an xterm style parameter, e.g. 5 of `ESC [ 1 ; 5 C`.
+    fn from_param(param: u32) -> Self {
+        Modifiers((param.saturating_sub(1) & 7) as u8)
+    }
+}
+
+impl BitOr for Modifiers {
+    type Output = Modifiers;
+
+    fn bitor(self, rhs: Modifiers) -> Modifiers {
+        Modifiers(self.0 | rhs.0)
+    }
+}
+
+/// A key with modifiers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyEvent {
+    pub key: Key,
+    pub modifiers: Modifiers,
+}
+
+impl KeyEvent {
+    /// Initialize a key event.
+    pub fn new(key: Key, modifiers: Modifiers) -> Self {
+        KeyEvent { key, modifiers }
+    }
+
+    /// Initialize a key event of Ctrl and `c`, e.g. `KeyEvent::ctrl('a')` is Ctrl-A.
+    pub fn ctrl(c: char) -> Self {
+        KeyEvent::new(Key::Char(c), Modifiers::CTRL)
+    }
+
+    /// Initialize a key event of Alt and `c`, e.g. `KeyEvent::alt('b')` is Alt-B.
+    pub fn alt(c: char) -> Self {
+        KeyEvent::new(Key::Char(c), Modifiers::ALT)
+    }
+
+    fn with(mut self, modifiers: Modifiers) -> Self {
+        self.modifiers = self.modifiers | modifiers;
+        self
+    }
+}
+
+impl From<Key> for KeyEvent {
+    fn from(key: Key) -> Self {
+        KeyEvent::new(key, Modifiers::NONE)
+    }
+}
+
+/// The reader of keys from a terminal.
+pub(crate) struct KeyReader {
+    fd: RawFd,
+    // The time to wait for the next byte of an escape sequence after `ESC`.
+    escape_timeout: Duration,
+}
+
+impl KeyReader {
+    pub(crate) fn new(fd: RawFd, escape_timeout: Duration) -> Self {
+        KeyReader { fd, escape_timeout }
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut buf = [0; 1];
+        loop {
+            match unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, 1) } {
+                1 => return Ok(buf[0]),
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                _ => {
+                    let e = io::Error::last_os_error();
+                    if e.kind() != io::ErrorKind::Interrupted {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Return true if a byte is readable within `timeout`.
+    fn poll(&self, timeout: Duration) -> io::Result<bool> {
+        let mut fds = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        loop {
+            match unsafe { libc::poll(&mut fds, 1, timeout) } {
+                -1 => {
+                    let e = io::Error::last_os_error();
+                    if e.kind() != io::ErrorKind::Interrupted {
+                        return Err(e);
+                    }
+                }
+                n => return Ok(n > 0),
+            }
+        }
+    }
+
+    /// Read a key.
+    pub(crate) fn read_key(&mut self) -> io::Result<KeyEvent> {
+        let b = self.read_byte()?;
+        if b == ESC {
+            self.read_escape()
+        } else {
+            self.read_char(b)
+        }
+    }
+
+    /// Decode a key which starts with `b` and is not an escape sequence.
+    fn read_char(&mut self, b: u8) -> io::Result<KeyEvent> {
+        let key = match b {
+            b'\r' => Key::Enter.into(),
+            b'\t' => Key::Tab.into(),
+            BACKSPACE => Key::Backspace.into(),
+            ESC => Key::Esc.into(),
+            0 => KeyEvent::ctrl(' '),
+            1..=26 => KeyEvent::ctrl((b'a' + b - 1) as char),
+            28..=31 => KeyEvent::ctrl((b'\\' + b - 28) as char),
+            0x20..=0x7e => Key::Char(b as char).into(),
+            _ => {
+                let mut buf = vec![b; unicode::utf8_len(b)];
+                for c in buf[1..].iter_mut() {
+                    *c = self.read_byte()?;
+                }
+                let c = String::from_utf8_lossy(&buf)
+                    .chars()
+                    .next()
+                    .unwrap_or('\u{fffd}');
+                Key::Char(c).into()
+            }
+        };
+        Ok(key)
+    }
+
+    /// Decode a key after `ESC`.
+    fn read_escape(&mut self) -> io::Result<KeyEvent> {
+        // A lone `ESC` is not followed by any byte in time.
+        if !self.poll(self.escape_timeout)? {
+            return Ok(Key::Esc.into());
+        }
+        match self.read_byte()? {
+            b'[' => self.read_csi(),
+            b'O' => self.read_ss3(),
+            // Alt and a key.
+            b => self.read_char(b).map(|key| key.with(Modifiers::ALT)),
+        }
+    }
+
+    /// Decode a sequence of `ESC [`.
+    fn read_csi(&mut self) -> io::Result<KeyEvent> {
+        let mut params = vec![0; 0];
+        let mut b = self.read_byte()?;
+        // The linux console sends `ESC [ [ A` as F1.
+        if b == b'[' {
+            let key = match self.read_byte()? {
+                c @ b'A'..=b'E' => Key::F(c - b'A' + 1),
+                _ => Key::Unknown,
+            };
+            return Ok(key.into());
+        }
+        while (0x30..=0x3f).contains(&b) {
+            params.push(b);
+            b = self.read_byte()?;
+        }
+        // Skip intermediate bytes.
+        while (0x20..=0x2f).contains(&b) {
+            b = self.read_byte()?;
+        }
+        let params: Vec<u32> = String::from_utf8_lossy(&params)
+            .split(';')
+            .map(|p| p.parse().unwrap_or(0))
+            .collect();
+        let modifiers = Modifiers::from_param(params.get(1).cloned().unwrap_or(1));
+        let key = match b {
+            b'A' => Key::Up,
+            b'B' => Key::Down,
+            b'C' => Key::Right,
+            b'D' => Key::Left,
+            b'H' => Key::Home,
+            b'F' => Key::End,
+            b'P' => Key::F(1),
+            b'Q' => Key::F(2),
+            b'R' => Key::F(3),
+            b'S' => Key::F(4),
+            b'Z' => return Ok(KeyEvent::new(Key::Tab, Modifiers::SHIFT)),
+            b'~' => match params[0] {
+                1 | 7 => Key::Home,
+                2 => Key::Insert,
+                3 => Key::Delete,
+                4 | 8 => Key::End,
+                5 => Key::PageUp,
+                6 => Key::PageDown,
+                n @ 11..=15 => Key::F((n - 10) as u8),
+                n @ 17..=21 => Key::F((n - 11) as u8),
+                n @ 23..=24 => Key::F((n - 12) as u8),
+                _ => Key::Unknown,
+            },
+            _ => Key::Unknown,
+        };
+        Ok(KeyEvent::new(key, modifiers))
+    }
+
+    /// Decode a sequence of `ESC O`.
+    fn read_ss3(&mut self) -> io::Result<KeyEvent> {
+        let key = match self.read_byte()? {
+            b'A' => Key::Up.into(),
+            b'B' => Key::Down.into(),
+            b'C' => Key::Right.into(),
+            b'D' => Key::Left.into(),
+            b'H' => Key::Home.into(),
+            b'F' => Key::End.into(),
+            b'M' => Key::Enter.into(),
+            b'P' => Key::F(1).into(),
+            b'Q' => Key::F(2).into(),
+            b'R' => Key::F(3).into(),
+            b'S' => Key::F(4).into(),
+            // rxvt sends Ctrl and arrows as `ESC O a` to `ESC O d`.
+            b'a' => KeyEvent::new(Key::Up, Modifiers::CTRL),
+            b'b' => KeyEvent::new(Key::Down, Modifiers::CTRL),
+            b'c' => KeyEvent::new(Key::Right, Modifiers::CTRL),
+            b'd' => KeyEvent::new(Key::Left, Modifiers::CTRL),
+            _ => Key::Unknown.into(),
+        };
+        Ok(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Read the keys from `input` which is fed through a pipe.
+    fn keys(input: &[u8]) -> Vec<KeyEvent> {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let written = unsafe { libc::write(fds[1], input.as_ptr() as *const _, input.len()) };
+        assert_eq!(written, input.len() as isize);
+        let mut reader = KeyReader::new(fds[0], Duration::from_millis(10));
+        let mut keys = vec![];
+        while reader.poll(Duration::from_millis(0)).unwrap() {
+            keys.push(reader.read_key().unwrap());
+        }
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+        keys
+    }
+
+    #[test]
+    fn csi() {
+        assert_eq!(
+            keys(b"\x1b[1;5C\x1b[15~\x1b[Z\x1b[3~\x1b[A\x1b[[A"),
+            [
+                KeyEvent::new(Key::Right, Modifiers::CTRL),
+                Key::F(5).into(),
+                KeyEvent::new(Key::Tab, Modifiers::SHIFT),
+                Key::Delete.into(),
+                Key::Up.into(),
+                Key::F(1).into(),
+            ]
+        );
+        assert_eq!(
+            keys(b"\x1b[1;3H\x1b[5;2~\x1b[200~"),
+            [
+                KeyEvent::new(Key::Home, Modifiers::ALT),
+                KeyEvent::new(Key::PageUp, Modifiers::SHIFT),
+                Key::Unknown.into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn ss3() {
+        assert_eq!(
+            keys(b"\x1bOH\x1bOF\x1bOP\x1bOM\x1bOc"),
+            [
+                Key::Home.into(),
+                Key::End.into(),
+                Key::F(1).into(),
+                Key::Enter.into(),
+                KeyEvent::new(Key::Right, Modifiers::CTRL),
+            ]
+        );
+    }
+
+    #[test]
+    fn alt_and_chars() {
+        assert_eq!(
+            keys("\x1bb\x1b\x7f\x01a\u{e9}\r\x1b".as_bytes()),
+            [
+                KeyEvent::alt('b'),
+                KeyEvent::new(Key::Backspace, Modifiers::ALT),
+                KeyEvent::ctrl('a'),
+                Key::Char('a').into(),
+                Key::Char('\u{e9}').into(),
+                Key::Enter.into(),
+                Key::Esc.into(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,51 @@
+use std::collections::VecDeque;
+
+// The maximum number of killed texts.
+const KILL_RING_LIMIT: usize = 60;
+
+/// The ring of killed texts to yank.
+#[derive(Default)]
+pub(crate) struct KillRing {
+    entries: VecDeque<Vec<u8>>,
+    // The index of the yanked text.
+    index: usize,
+}
+
+impl KillRing {
+    /// Push a killed `text`. If `join` is true, `text` is joined to the last killed text,
+    /// in front of it if `prepend` is true.
+    pub(crate) fn kill(&mut self, text: &[u8], prepend: bool, join: bool) {
+        if text.is_empty() {
+            return;
+        }
+        match self.entries.front_mut() {
+            Some(entry) if join => {
+                if prepend {
+                    entry.splice(0..0, text.iter().cloned());
+                } else {
+                    entry.extend(text);
+                }
+            }
+            _ => {
+                self.entries.push_front(text.to_vec());
+                self.entries.truncate(KILL_RING_LIMIT);
+            }
+        }
+        self.index = 0;
+    }
+
+    /// Return the last killed text.
+    pub(crate) fn yank(&mut self) -> Option<&Vec<u8>> {
+        self.index = 0;
+        self.entries.front()
+    }
+
+    /// Rotate the ring and return the killed text before the last yanked text.
+    pub(crate) fn yank_pop(&mut self) -> Option<&Vec<u8>> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        self.index = (self.index + 1) % self.entries.len();
+        self.entries.get(self.index)
+    }
+}
@@ -9,8 +9,7 @@
 //!
 //! # Example
 //! ```no_run
-//! use interaction::InteractionBuilder;
-//! use std::io;
+//! use interaction::{InteractionBuilder, ReadlineError};
 //!
 //! fn main() {
 //!     let history_file = "./.example_history";
@@ -29,7 +28,10 @@
 //!             Ok(input) => {
 //!                 // write any code.
 //!             }
-//!             Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+//!             Err(ReadlineError::Interrupted) => {
+//!                 continue;
+//!             }
+//!             Err(ReadlineError::Eof) => {
 //!                 inter.save_history(history_file).unwrap();
 //!                 break;
 //!             }
@@ -41,15 +43,41 @@
 //! }
 //! ```
 
-use libc;
-use std::collections::VecDeque;
-use std::fs::File;
+mod completion;
+mod expansion;
+mod history;
+mod keymap;
+mod keys;
+mod kill_ring;
+mod unicode;
+
+pub use completion::{Candidate, Completer, CompletionMode, FilenameCompleter};
+pub use history::{Duplicates, Entry, History};
+pub use keymap::{Command, Editor};
+pub use keys::{Key, KeyEvent, Modifiers};
+
+use completion::CompletionCompleter;
+use keymap::{Binding, Keymap};
+use keys::KeyReader;
+use kill_ring::KillRing;
+
+use std::env;
+use std::error;
+use std::fmt;
 use std::io;
-use std::io::{Read, Write};
+use std::io::{BufRead, Write};
+use std::mem;
+use std::ops::Range;
 use std::os::unix::io::RawFd;
 use std::path::Path;
+use std::time::Duration;
 use termios::*;
 
+const DEFAULT_ESCAPE_TIMEOUT: Duration = Duration::from_millis(100);
+const DEFAULT_COMPLETION_QUERY_ITEMS: usize = 100;
+// The maximum number of rows of the completion menu.
+const MENU_ROWS: usize = 10;
+
 fn get_stdin_fd() -> RawFd {
     libc::STDIN_FILENO
 }
@@ -58,143 +86,95 @@ fn get_stdout_fd() -> RawFd {
     libc::STDOUT_FILENO
 }
 
-fn get_col() -> u16 {
+fn is_tty(fd: RawFd) -> bool {
+    unsafe { libc::isatty(fd) == 1 }
+}
+
+/// Return true if the terminal does not support cursor addressing.
+fn is_dumb_term() -> bool {
+    match env::var("TERM") {
+        Ok(term) => term.is_empty() || term == "dumb" || term == "cons25" || term == "emacs",
+        Err(_) => true,
+    }
+}
+
+fn get_winsize() -> libc::winsize {
     let mut winsize = libc::winsize {
         ws_row: 0,
         ws_col: 0,
         ws_xpixel: 0,
         ws_ypixel: 0,
     };
-    if unsafe { libc::ioctl(get_stdout_fd(), libc::TIOCGWINSZ, &mut winsize) } == 0 {
-        winsize.ws_col
-    } else {
-        80
+    unsafe { libc::ioctl(get_stdout_fd(), libc::TIOCGWINSZ, &mut winsize) };
+    winsize
+}
+
+fn get_col() -> u16 {
+    match get_winsize().ws_col {
+        0 => 80,
+        col => col,
     }
 }
 
-mod keys {
-    pub(crate) const CTRL_A: u8 = 1;
-    pub(crate) const CTRL_B: u8 = 2;
-    pub(crate) const CTRL_C: u8 = 3;
-    pub(crate) const CTRL_D: u8 = 4;
-    pub(crate) const CTRL_E: u8 = 5;
-    pub(crate) const CTRL_F: u8 = 6;
-    pub(crate) const CTRL_H: u8 = 8;
-    pub(crate) const CTRL_I: u8 = 9;
-    pub(crate) const CTRL_J: u8 = 10;
-    pub(crate) const CTRL_K: u8 = 11;
-    pub(crate) const CTRL_L: u8 = 12;
-    pub(crate) const CTRL_M: u8 = 13;
-    pub(crate) const ESC: u8 = 27;
-    pub(crate) const ONE: u8 = 49;
-    pub(crate) const TWO: u8 = 50;
-    pub(crate) const THREE: u8 = 51;
-    pub(crate) const FOUR: u8 = 52;
-    pub(crate) const FIVE: u8 = 53;
-    pub(crate) const SIX: u8 = 54;
-    pub(crate) const A: u8 = 65;
-    pub(crate) const B: u8 = 66;
-    pub(crate) const C: u8 = 67;
-    pub(crate) const D: u8 = 68;
-    // This char is `[`.
-    pub(crate) const LEFT_BRACKET: u8 = 91;
-    pub(crate) const BACKSPACE: u8 = 127;
+fn get_row() -> u16 {
+    match get_winsize().ws_row {
+        0 => 24,
+        row => row,
+    }
 }
 
 /// The type is a callback for completion.
 pub type Completion = fn(&Vec<u8>, &mut Vec<Vec<u8>>);
 
-/// The struct is to management the history of command line.
-pub struct History {
-    commands: VecDeque<Vec<u8>>,
-    position: usize,
-    // if limit is 0, history is unlimited.
-    limit: usize,
+/// The error type of [Interaction::line](struct.Interaction.html#method.line).
+#[derive(Debug)]
+pub enum ReadlineError {
+    /// The line is canceled by Ctrl-C. The interaction can get the next line.
+    Interrupted,
+    /// The end of input, e.g. Ctrl-D is pressed on an empty line.
+    Eof,
+    /// The history expansion failed, e.g. `!foo: event not found`.
+    Expansion(String),
+    /// An I/O error.
+    Io(io::Error),
 }
 
-impl History {
-    /// Initialize history. `limit` is the maximum size of history. If limit is zero, unlimited.
-    pub fn new(limit: usize) -> Self {
-        History {
-            commands: VecDeque::new(),
-            position: 0,
-            limit,
-        }
-    }
-
-    /// Return a next command from the current line.
-    pub(crate) fn next(&mut self) -> Option<&Vec<u8>> {
-        if self.commands.len() == 0 || self.position == self.commands.len() {
-            None
-        } else {
-            self.position += 1;
-            self.commands.get(self.position)
-        }
-    }
-
-    /// Return a previous command from the current line.
-    pub(crate) fn prev(&mut self) -> Option<&Vec<u8>> {
-        if self.commands.len() == 0 || self.position == 0 {
-            None
-        } else {
-            self.position -= 1;
-            self.commands.get(self.position)
+impl fmt::Display for ReadlineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadlineError::Interrupted => write!(f, "interrupted"),
+            ReadlineError::Eof => write!(f, "end of input"),
+            ReadlineError::Expansion(message) => write!(f, "{}", message),
+            ReadlineError::Io(e) => e.fmt(f),
         }
     }
+}
 
-    fn _append(&mut self, history: Vec<u8>) {
-        if self.limit > 0 && self.commands.len() == self.limit {
-            self.commands.pop_front();
+impl error::Error for ReadlineError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ReadlineError::Io(e) => Some(e),
+            _ => None,
         }
-        self.commands.push_back(history);
     }
+}
 
-    /// Append a new command.
-    pub fn append(&mut self, history: Vec<u8>) {
-        self._append(history);
-        self.position = self.commands.len();
+impl From<io::Error> for ReadlineError {
+    fn from(e: io::Error) -> Self {
+        ReadlineError::Io(e)
     }
+}
 
-    /// Load a history from the given `file_path`.
-    pub fn load<P: AsRef<Path>>(&mut self, file_path: P) -> io::Result<()> {
-        let mut file = match File::open(file_path) {
-            Ok(file) => file,
-            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
-            Err(e) => return Err(e),
-        };
-        let mut buffer = vec![0; 4096];
-        let mut cmd = vec![0; 0];
-        loop {
-            let n = file.read(&mut buffer)?;
-            if n == 0 {
-                break;
+impl From<ReadlineError> for io::Error {
+    fn from(e: ReadlineError) -> Self {
+        match e {
+            ReadlineError::Interrupted => io::ErrorKind::Interrupted.into(),
+            ReadlineError::Eof => io::ErrorKind::UnexpectedEof.into(),
+            ReadlineError::Expansion(message) => {
+                io::Error::new(io::ErrorKind::InvalidInput, message)
             }
-            for c in buffer[..n].iter() {
-                if *c == b'\n' && cmd.len() > 0 {
-                    self._append(cmd);
-                    cmd = vec![0; 0];
-                    continue;
-                }
-                cmd.push(*c);
-            }
-        }
-        if cmd.len() > 0 {
-            self._append(cmd);
-        }
-        if self.commands.len() > 0 {
-            self.position = self.commands.len();
+            ReadlineError::Io(e) => e,
         }
-        Ok(())
-    }
-
-    /// Save the history to the given `file_path`.
-    pub fn save<P: AsRef<Path>>(&mut self, file_path: P) -> io::Result<()> {
-        File::create(file_path).and_then(|mut file| {
-            for cmd in self.commands.iter() {
-                file.write_all(cmd).and(file.write_all(b"\n"))?;
-            }
-            file.flush()
-        })
     }
 }
 
@@ -203,31 +183,80 @@ struct Line<'a> {
     position: usize,
     buffer: &'a mut Vec<u8>,
     prompt: &'a [u8],
-    completion: &'a Option<Completion>,
+    completer: &'a mut Option<Box<dyn Completer + Send>>,
+    completion_mode: CompletionMode,
+    // The number of completions to ask before listing them.
+    query_items: usize,
+    // If true, the last command completed the line in the list mode.
+    completed: bool,
+    // The rows of the completion menu below the line.
+    menu: Vec<Vec<u8>>,
     multi: bool,
+    // If true, the terminal is dumb and the line is refreshed without escape sequences.
+    dumb: bool,
+    overwrite: &'a mut bool,
     row: usize,
+    // The number of columns written by the last refresh in dumb mode.
+    width: usize,
     history: &'a mut History,
+    keymap: &'a Keymap,
+    keys: KeyReader,
+    kill_ring: &'a mut KillRing,
+    // If true, the last command killed text.
+    killed: bool,
+    // The range of the text inserted by the last yank.
+    yanked: Option<Range<usize>>,
+    word_chars: &'a [char],
+    // Snapshots of the buffer and the cursor to undo and redo.
+    undo: Vec<(Vec<u8>, usize)>,
+    redo: Vec<(Vec<u8>, usize)>,
+    // If true, the last change was a typed char.
+    inserting: bool,
 }
 
 impl<'a> Line<'a> {
-    fn new(
-        buffer: &'a mut Vec<u8>,
-        prompt: &'a [u8],
-        completion: &'a Option<Completion>,
-        multi: bool,
-        history: &'a mut History,
-    ) -> Self {
+    fn new(buffer: &'a mut Vec<u8>, inter: &'a mut Interaction) -> Self {
         let backup = Termios::from_fd(get_stdin_fd()).unwrap();
         Line::enable_raw_mode().unwrap();
+        let Interaction {
+            prompt,
+            completer,
+            multi,
+            overwrite,
+            history,
+            escape_timeout,
+            keymap,
+            kill_ring,
+            word_chars,
+            completion_mode,
+            query_items,
+            ..
+        } = inter;
         Line {
             backup,
-            position: 0,
+            position: buffer.len(),
             buffer,
             prompt,
-            completion,
-            multi,
+            completer,
+            completion_mode: *completion_mode,
+            query_items: *query_items,
+            completed: false,
+            menu: vec![],
+            multi: *multi,
+            dumb: is_dumb_term(),
+            overwrite,
             row: 0,
+            width: 0,
             history,
+            keymap,
+            keys: KeyReader::new(get_stdin_fd(), *escape_timeout),
+            kill_ring,
+            killed: false,
+            yanked: None,
+            word_chars,
+            undo: vec![],
+            redo: vec![],
+            inserting: false,
         }
     }
 
@@ -249,22 +278,25 @@ impl<'a> Line<'a> {
         tcsetattr(fd, TCSANOW, &self.backup).and(tcflush(fd, TCIOFLUSH))
     }
 
-    fn refresh_single_line(&self) -> io::Result<()> {
+    fn refresh_single_line(&self, prompt: &[u8]) -> io::Result<()> {
         let mut stdout = io::stdout();
-        stdout
-            .write_all(
-                &[
-                    b"\x1b[0G\x1b[K",
-                    self.prompt,
-                    &self.buffer[..],
-                    format!("\r\x1b[{}C", self.position + self.prompt.len()).as_bytes(),
-                ]
-                .concat(),
-            )
-            .and(stdout.flush())
+        let pos = unicode::width(prompt) + unicode::width(&self.buffer[..self.position]);
+        let mut seq = [b"\x1b[0G\x1b[J", prompt, &self.buffer[..]].concat();
+        for row in self.menu.iter() {
+            seq.extend(b"\r\n");
+            seq.extend(row);
+        }
+        seq.extend(b"\r");
+        if !self.menu.is_empty() {
+            seq.extend(format!("\x1b[{}A", self.menu.len()).as_bytes());
+        }
+        if pos > 0 {
+            seq.extend(format!("\x1b[{}C", pos).as_bytes());
+        }
+        stdout.write_all(&seq).and(stdout.flush())
     }
 
-    fn refresh_multi_line(&mut self) -> io::Result<()> {
+    fn refresh_multi_line(&mut self, prompt: &[u8]) -> io::Result<()> {
         let col = get_col() as usize;
         let mut stdout = io::stdout();
         if self.row == 0 {
@@ -272,293 +304,686 @@ impl<'a> Line<'a> {
         } else {
             stdout.write_all(format!("\x1b[0G\x1b[{}A\x1b[J", self.row).as_bytes())?;
         }
+        let prompt = String::from_utf8_lossy(prompt);
+        let buffer = String::from_utf8_lossy(self.buffer);
         let mut cnt = 0;
         let mut row: usize = 0;
-        for c in self.prompt.iter().chain(self.buffer.iter()) {
-            stdout.write_all(&[*c])?;
-            cnt += 1;
-            if cnt == col {
+        // The row and the column of the cursor.
+        let mut cursor = None;
+        let chars = unicode::graphemes(&prompt)
+            .map(|(_, g)| (None, g))
+            .chain(unicode::graphemes(&buffer).map(|(i, g)| (Some(i), g)));
+        for (offset, g) in chars {
+            let w = unicode::grapheme_width(g);
+            // A wide char which does not fit in the rest of the row goes to the next row.
+            if cnt + w > col && cnt > 0 {
                 stdout.write_all(b"\n\x1b[0G")?;
                 cnt = 0;
                 row += 1;
             }
+            if offset == Some(self.position) {
+                cursor = Some((row, cnt));
+            }
+            stdout.write_all(g.as_bytes())?;
+            cnt += w;
+            if cnt >= col {
+                stdout.write_all(b"\n\x1b[0G")?;
+                cnt = 0;
+                row += 1;
+            }
+        }
+        let (cursor_row, cursor_col) = cursor.unwrap_or((row, cnt));
+        for menu_row in self.menu.iter() {
+            stdout.write_all(b"\r\n").and(stdout.write_all(menu_row))?;
         }
         stdout.write_all(b"\r")?;
-        if row == 0 {
+        let up = row + self.menu.len();
+        if up == 0 {
             stdout.write_all(b"\x1b[0G")?;
         } else {
-            stdout.write_all(format!("\x1b[0G\x1b[{}A", row).as_bytes())?;
+            stdout.write_all(format!("\x1b[0G\x1b[{}A", up).as_bytes())?;
         }
-        let pos = self.prompt.len() + self.position;
-        let m = pos % col;
-        self.row = pos / col;
+        self.row = cursor_row;
         if self.row > 0 {
             stdout.write_all(format!("\x1b[{}B", self.row).as_bytes())?;
         }
-        if m > 0 {
-            stdout.write_all(format!("\x1b[{}C", m).as_bytes())?;
+        if cursor_col > 0 {
+            stdout.write_all(format!("\x1b[{}C", cursor_col).as_bytes())?;
         }
         stdout.flush()?;
         Ok(())
     }
 
+    fn refresh_dumb_line(&mut self, prompt: &[u8]) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        let width = unicode::width(prompt) + unicode::width(self.buffer);
+        // Overwrite the rest of the last line with spaces, and write the line to the cursor
+        // again to put the cursor.
+        let mut seq = [b"\r", prompt, &self.buffer[..]].concat();
+        if width < self.width {
+            seq.resize(seq.len() + self.width - width, b' ');
+        }
+        seq.extend(b"\r");
+        seq.extend(prompt);
+        seq.extend(&self.buffer[..self.position]);
+        self.width = width;
+        stdout.write_all(&seq).and(stdout.flush())
+    }
+
     fn refresh_line(&mut self) -> io::Result<()> {
-        if self.multi {
-            self.refresh_multi_line()
+        let prompt = self.prompt;
+        self.refresh_line_with(prompt)
+    }
+
+    /// Refresh the line with `prompt` instead of the prompt of the interaction.
+    fn refresh_line_with(&mut self, prompt: &[u8]) -> io::Result<()> {
+        if self.dumb {
+            self.refresh_dumb_line(prompt)
+        } else if self.multi {
+            self.refresh_multi_line(prompt)
         } else {
-            self.refresh_single_line()
+            self.refresh_single_line(prompt)
         }
     }
 
-    fn completion(&mut self, callback: &Completion) -> io::Result<u8> {
-        let mut completions = Vec::new();
-        callback(self.buffer, &mut completions);
-        if completions.len() == 0 {
-            return Ok(0);
+    /// Replace the buffer with `line` and move the cursor end of line.
+    /// Invalid UTF-8 sequences are replaced with `U+FFFD`.
+    fn replace_buffer(&mut self, line: &[u8]) {
+        self.buffer.clear();
+        self.buffer.extend(String::from_utf8_lossy(line).as_bytes());
+        self.position = self.buffer.len();
+    }
+
+    /// Return true if `c` is a part of a word.
+    fn is_word_char(&self, c: char) -> bool {
+        c.is_alphanumeric() || self.word_chars.contains(&c)
+    }
+
+    /// Kill the text of `start..end` and move the cursor to `start`.
+    /// If `join` is true, the text is joined to the last killed text in the kill ring.
+    fn kill(&mut self, start: usize, end: usize, join: bool) {
+        let text: Vec<u8> = self.buffer.drain(start..end).collect();
+        let prepend = end <= self.position;
+        self.kill_ring.kill(&text, prepend, join);
+        self.position = start;
+        self.killed = true;
+    }
+
+    /// Insert `text` at the cursor as a yanked text.
+    fn yank(&mut self, text: &[u8]) {
+        let start = self.position;
+        self.buffer.splice(start..start, text.iter().cloned());
+        self.position += text.len();
+        self.yanked = Some(start..self.position);
+    }
+
+    /// Cycle the completions. Return the key which stops the completion, or `None` if there is
+    /// no completion.
+    fn completion(&mut self) -> io::Result<Option<KeyEvent>> {
+        let (range, completions) = match self.completer {
+            Some(completer) => completer.complete(self.buffer, self.position),
+            None => return Ok(None),
+        };
+        if completions.is_empty() {
+            return Ok(None);
         }
-        let mut stdin = io::stdin();
-        let bk = self.buffer.clone();
-        let mut buf = vec![0; 1];
+        let end = unicode::floor_char_boundary(self.buffer, range.end);
+        let start = unicode::floor_char_boundary(self.buffer, range.start.min(end));
+        let bk = (self.buffer.clone(), self.position);
         loop {
             for comp in completions.iter() {
-                self.buffer.clear();
-                self.buffer.extend(comp);
-                self.position = self.buffer.len();
+                let comp = String::from_utf8_lossy(&comp.replacement);
+                let mut buffer = bk.0.clone();
+                buffer.splice(start..end, comp.bytes());
+                *self.buffer = buffer;
+                self.position = start + comp.len();
                 self.refresh_line()?;
 
-                let n = stdin.read(&mut buf)?;
-                assert_eq!(n, 1);
-
-                match buf[0] {
-                    keys::CTRL_I => {
+                let key = self.keys.read_key()?;
+                match (key.key, self.keymap.get(&key)) {
+                    (_, Some(Command::Complete)) => {
                         continue;
                     }
-                    keys::ESC => {
-                        self.buffer.clear();
-                        self.buffer.extend(&bk);
-                        self.position = self.buffer.len();
+                    (Key::Esc, _) => {
+                        *self.buffer = bk.0;
+                        self.position = bk.1;
                         self.refresh_line()?;
-                        return Ok(buf[0]);
+                        return Ok(Some(key));
                     }
                     _ => {
-                        return Ok(buf[0]);
+                        return Ok(Some(key));
                     }
                 }
             }
         }
     }
 
-    fn fetch(mut self) -> io::Result<()> {
-        let mut stdin = io::stdin();
-
-        self.refresh_line()?;
+    /// Insert the longest common prefix of the completions. If `listed` is true and there is
+    /// nothing to insert, list the completions.
+    fn complete_prefix(&mut self, listed: bool) -> io::Result<()> {
+        let (range, completions) = match self.completer {
+            Some(completer) => completer.complete(self.buffer, self.position),
+            None => return Ok(()),
+        };
+        if completions.is_empty() {
+            return self.bell();
+        }
+        let end = unicode::floor_char_boundary(self.buffer, range.end);
+        let start = unicode::floor_char_boundary(self.buffer, range.start.min(end));
+        let prefix = completion::common_prefix(&completions);
+        let prefix = String::from_utf8_lossy(&prefix);
+        let text = &self.buffer[start..end];
+        if prefix.len() > text.len() || (completions.len() == 1 && prefix.as_bytes() != text) {
+            self.buffer.splice(start..end, prefix.bytes());
+            self.position = start + prefix.len();
+            self.refresh_line()
+        } else if completions.len() == 1 {
+            Ok(())
+        } else if listed {
+            self.list_completions(&completions)
+        } else {
+            self.bell()
+        }
+    }
 
-        let mut buf = vec![0; 1];
-        let mut tmp = vec![0; 0];
-        let mut used = false;
+    /// Select a completion in the menu below the line. Return the key which stops the menu, or
+    /// `None` if the key is consumed.
+    fn completion_menu(&mut self) -> io::Result<Option<KeyEvent>> {
+        let (range, completions) = match self.completer {
+            Some(completer) => completer.complete(self.buffer, self.position),
+            None => return Ok(None),
+        };
+        if completions.is_empty() {
+            self.bell()?;
+            return Ok(None);
+        }
+        let end = unicode::floor_char_boundary(self.buffer, range.end);
+        let start = unicode::floor_char_boundary(self.buffer, range.start.min(end));
+        let bk = (self.buffer.clone(), self.position);
+        let layout = completion::Layout::new(&completions, get_col() as usize);
+        let len = completions.len();
+        let columns = layout.columns;
+        let height = MENU_ROWS.min(get_row().saturating_sub(2) as usize).max(1);
+        let mut selected = 0;
+        let mut top = 0;
         loop {
-            let n = stdin.read(&mut buf)?;
-            assert_eq!(n, 1);
-
-            if buf[0] == keys::ESC {
-                let mut buf2 = vec![0; 3];
-                let n = stdin.read(&mut buf2[0..1])?;
-                assert_eq!(n, 1);
-                match buf2[0] {
-                    // arrows, home, end or del
-                    keys::LEFT_BRACKET => {
-                        let n = stdin.read(&mut buf2[1..2])?;
-                        assert_eq!(n, 1);
-                        match buf2[1] {
-                            // HOME
-                            keys::ONE => {
-                                let _ = stdin.read(&mut buf2[2..3])?;
-                                buf[0] = keys::CTRL_A;
-                            }
-                            // INS
-                            keys::TWO => {
-                                let _ = stdin.read(&mut buf2[2..3])?;
-                                continue;
-                            }
-                            // DEL
-                            keys::THREE => {
-                                let _ = stdin.read(&mut buf2[2..3])?;
-                                if self.position < self.buffer.len() {
-                                    buf[0] = keys::CTRL_D;
-                                } else {
-                                    continue;
-                                }
-                            }
-                            // END
-                            keys::FOUR => {
-                                let _ = stdin.read(&mut buf2[2..3])?;
-                                buf[0] = keys::CTRL_E;
-                            }
-                            // PgUp
-                            keys::FIVE => {
-                                let _ = stdin.read(&mut buf2[2..3])?;
-                                continue;
-                            }
-                            // PgDn
-                            keys::SIX => {
-                                let _ = stdin.read(&mut buf2[2..3])?;
-                                continue;
-                            }
-                            // Up
-                            keys::A => match self.history.prev() {
-                                Some(cmd) => {
-                                    if !used {
-                                        tmp.extend(&self.buffer[..]);
-                                        used = true;
-                                    }
-                                    self.buffer.clear();
-                                    self.buffer.extend(cmd);
-                                    self.position = self.buffer.len();
-                                    self.refresh_line()?;
-                                    continue;
-                                }
-                                None => {
-                                    continue;
-                                }
-                            },
-                            // Down
-                            keys::B => match self.history.next() {
-                                Some(cmd) => {
-                                    self.buffer.clear();
-                                    self.buffer.extend(cmd);
-                                    self.position = self.buffer.len();
-                                    self.refresh_line()?;
-                                    continue;
-                                }
-                                None => {
-                                    if used {
-                                        used = false;
-                                        self.buffer.clear();
-                                        self.buffer.extend(&tmp[..]);
-                                        self.position = self.buffer.len();
-                                        tmp.clear();
-                                        self.refresh_line()?;
-                                    }
-                                    continue;
-                                }
-                            },
-                            // Right
-                            keys::C => {
-                                buf[0] = keys::CTRL_F;
-                            }
-                            // Left
-                            keys::D => {
-                                buf[0] = keys::CTRL_B;
-                            }
-                            _ => {
-                                buf[0] = buf2[1];
-                            }
-                        }
+            let comp = String::from_utf8_lossy(&completions[selected].replacement);
+            let mut buffer = bk.0.clone();
+            buffer.splice(start..end, comp.bytes());
+            *self.buffer = buffer;
+            self.position = start + comp.len();
+            if len == 1 {
+                self.refresh_line()?;
+                return Ok(None);
+            }
+            // Scroll the menu to show the selected candidate.
+            let row = selected / columns;
+            if row < top {
+                top = row;
+            } else if row >= top + height {
+                top = row + 1 - height;
+            }
+            self.menu = layout.menu(&completions, selected, top, height);
+            self.refresh_line()?;
+
+            let key = self.keys.read_key()?;
+            let command = self.keymap.get(&key);
+            selected = match (key.key, key.modifiers, command) {
+                (Key::Tab, Modifiers::SHIFT, _) | (Key::Left, Modifiers::NONE, _) => {
+                    (selected + len - 1) % len
+                }
+                (_, _, Some(Command::Complete)) | (Key::Right, Modifiers::NONE, _) => {
+                    (selected + 1) % len
+                }
+                (Key::Down, Modifiers::NONE, _) => {
+                    if selected + columns < len {
+                        selected + columns
+                    } else {
+                        selected % columns
                     }
-                    _ => {
-                        // handle to esc
-                        // ...
-                        buf[0] = buf2[0];
+                }
+                (Key::Up, Modifiers::NONE, _) => {
+                    if selected >= columns {
+                        selected - columns
+                    } else {
+                        // The same column in the last row.
+                        let column = selected % columns;
+                        (len - 1 - column) / columns * columns + column
                     }
                 }
+                (Key::PageDown, Modifiers::NONE, _) => (selected + columns * height).min(len - 1),
+                (Key::PageUp, Modifiers::NONE, _) => selected.saturating_sub(columns * height),
+                (Key::Enter, _, _) => {
+                    self.menu.clear();
+                    self.refresh_line()?;
+                    return Ok(None);
+                }
+                (Key::Esc, _, _) | (_, _, Some(Command::Abort)) => {
+                    *self.buffer = bk.0;
+                    self.position = bk.1;
+                    self.menu.clear();
+                    self.refresh_line()?;
+                    return Ok(None);
+                }
+                _ => {
+                    self.menu.clear();
+                    self.refresh_line()?;
+                    return Ok(Some(key));
+                }
+            };
+        }
+    }
+
+    /// List the completions below the line.
+    fn possible_completions(&mut self) -> io::Result<()> {
+        let completions = match self.completer {
+            Some(completer) => completer.complete(self.buffer, self.position).1,
+            None => return Ok(()),
+        };
+        if completions.is_empty() {
+            return self.bell();
+        }
+        self.list_completions(&completions)
+    }
+
+    /// List `completions` below the line, and refresh the line after them. If there are many
+    /// completions, ask whether to list them.
+    fn list_completions(&mut self, completions: &[Candidate]) -> io::Result<()> {
+        let rows = completion::list(completions, get_col() as usize);
+        let position = self.position;
+        self.new_line(b"")?;
+        self.position = position;
+        let mut stdout = io::stdout();
+        if self.query_items > 0 && completions.len() >= self.query_items {
+            let question = format!("Display all {} possibilities? (y or n)", completions.len());
+            stdout.write_all(question.as_bytes()).and(stdout.flush())?;
+            let yes = loop {
+                match self.keys.read_key()?.key {
+                    Key::Char('y') | Key::Char('Y') | Key::Char(' ') => break true,
+                    Key::Char('n') | Key::Char('N') | Key::Esc | Key::Backspace => break false,
+                    _ => self.bell()?,
+                }
+            };
+            stdout.write_all(b"\r\n")?;
+            if !yes {
+                return self.redraw();
             }
+        }
+        for row in rows {
+            stdout.write_all(&row).and(stdout.write_all(b"\r\n"))?;
+        }
+        self.redraw()
+    }
 
-            // Tab
-            if buf[0] == keys::CTRL_I {
-                match self.completion {
-                    Some(callback) => {
-                        let c = self.completion(callback)?;
-                        if c == 0 {
-                            continue;
-                        }
-                        buf[0] = c;
+    /// Refresh the line below the output after it.
+    fn redraw(&mut self) -> io::Result<()> {
+        self.row = 0;
+        self.width = 0;
+        self.refresh_line()
+    }
+
+    /// Ring the bell.
+    fn bell(&self) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        stdout.write_all(b"\x07").and(stdout.flush())
+    }
+
+    /// Search the history incrementally. Return the key which stops the search, or `None` if
+    /// the key is consumed.
+    fn search(&mut self, reverse: bool) -> io::Result<Option<KeyEvent>> {
+        let bk = (self.buffer.clone(), self.position);
+        let mut reverse = reverse;
+        let mut query = String::new();
+        // The index of the matched command. `None` is the current line.
+        let mut index: Option<usize> = None;
+        let mut failed = false;
+        loop {
+            let prompt = format!(
+                "({}{}i-search)`{}': ",
+                if failed { "failed " } else { "" },
+                if reverse { "reverse-" } else { "" },
+                query
+            );
+            self.refresh_line_with(prompt.as_bytes())?;
+
+            let key = self.keys.read_key()?;
+            let start = match self.keymap.get(&key) {
+                Some(Command::ReverseSearchHistory) => {
+                    reverse = true;
+                    match index {
+                        Some(0) => continue,
+                        Some(i) => Some(i - 1),
+                        None => self.history.commands.len().checked_sub(1),
                     }
-                    None => continue,
                 }
+                Some(Command::ForwardSearchHistory) => {
+                    reverse = false;
+                    index.map(|i| i + 1)
+                }
+                Some(Command::BackwardDeleteChar) => {
+                    query.pop();
+                    index
+                }
+                Some(Command::SelfInsert) => match key.key {
+                    Key::Char(c) => {
+                        query.push(c);
+                        index.or_else(|| self.history.commands.len().checked_sub(1))
+                    }
+                    _ => continue,
+                },
+                Some(Command::Abort) => {
+                    self.replace_buffer(&bk.0);
+                    self.position = bk.1;
+                    self.refresh_line()?;
+                    return Ok(None);
+                }
+                _ => {
+                    if let Some(i) = index {
+                        self.history.position = i;
+                    }
+                    self.refresh_line()?;
+                    return Ok(if key.key == Key::Esc { None } else { Some(key) });
+                }
+            };
+            failed = false;
+            if query.is_empty() {
+                continue;
+            }
+            match start.and_then(|start| self.history.search_from(query.as_bytes(), start, reverse))
+            {
+                Some((i, offset)) => {
+                    let cmd = self.history.commands[i].command.clone();
+                    index = Some(i);
+                    self.replace_buffer(&cmd);
+                    self.position = unicode::floor_char_boundary(self.buffer, offset);
+                }
+                None => failed = true,
             }
+        }
+    }
 
-            match buf[0] {
-                // Move the cursor start of line.
-                keys::CTRL_A => {
+    /// Read keys until they are bound to a command.
+    fn read_command(&mut self, first: Option<KeyEvent>) -> io::Result<(KeyEvent, Command)> {
+        let mut keys: Vec<KeyEvent> = first.into_iter().collect();
+        loop {
+            if keys.is_empty() {
+                keys.push(self.keys.read_key()?);
+            }
+            match self.keymap.lookup(&keys) {
+                Binding::Command(command) => return Ok((keys[keys.len() - 1], command)),
+                Binding::Prefix => keys.push(self.keys.read_key()?),
+                Binding::None => keys.clear(),
+            }
+        }
+    }
+
+    /// Save `snapshot` to undo. Consecutive typed chars are saved at once.
+    fn save_undo(&mut self, snapshot: (Vec<u8>, usize), inserting: bool) {
+        if !(inserting && self.inserting) {
+            self.undo.push(snapshot);
+        }
+        self.redo.clear();
+    }
+
+    fn fetch(mut self) -> Result<(), ReadlineError> {
+        self.refresh_line()?;
+
+        let mut tmp = vec![0; 0];
+        let mut used = false;
+        // The key which stops the completion.
+        let mut next = None;
+        loop {
+            let (key, command) = self.read_command(next.take())?;
+            let killed = mem::replace(&mut self.killed, false);
+            let completed = mem::replace(&mut self.completed, false);
+            let yanked = self.yanked.take();
+            let snapshot = (self.buffer.clone(), self.position);
+            let mut inserting = false;
+
+            match command {
+                Command::BeginningOfLine => {
                     self.position = 0;
                     self.refresh_line()?;
                 }
-                // Move the cursor forward 1 column.
-                keys::CTRL_B => {
-                    if self.position == 0 {
-                        continue;
+                Command::BackwardChar => {
+                    if self.position > 0 {
+                        self.position = unicode::prev_grapheme(self.buffer, self.position);
+                        self.refresh_line()?;
                     }
-                    self.position -= 1;
-                    self.refresh_line()?;
                 }
-                // Exit the process.
-                keys::CTRL_C => {
-                    self.disable_raw_mode()?;
-                    return Err(io::ErrorKind::Interrupted.into());
-                }
-                keys::CTRL_D => {
-                    // If the buffer is empty, exit the process.
-                    if self.buffer.len() == 0 {
-                        self.disable_raw_mode()?;
-                        return Err(io::ErrorKind::Interrupted.into());
-                    // Delete a char at the cursor.
-                    } else if self.position < self.buffer.len() {
-                        self.buffer.remove(self.position);
+                Command::Interrupt => {
+                    self.new_line(b"^C")?;
+                    return Err(ReadlineError::Interrupted);
+                }
+                Command::DeleteCharOrEof if self.buffer.is_empty() => {
+                    self.new_line(b"")?;
+                    return Err(ReadlineError::Eof);
+                }
+                Command::DeleteChar | Command::DeleteCharOrEof => {
+                    if self.position < self.buffer.len() {
+                        let end = unicode::next_grapheme(self.buffer, self.position);
+                        self.buffer.drain(self.position..end);
                         self.refresh_line()?;
                     }
                 }
-                // Move the cursor end of line.
-                keys::CTRL_E => {
+                Command::EndOfLine => {
                     self.position = self.buffer.len();
                     self.refresh_line()?;
                 }
-                // Move the cursor backward 1 column.
-                keys::CTRL_F => {
-                    if self.position == self.buffer.len() {
-                        continue;
+                Command::ForwardChar => {
+                    if self.position < self.buffer.len() {
+                        self.position = unicode::next_grapheme(self.buffer, self.position);
+                        self.refresh_line()?;
                     }
-                    self.position += 1;
+                }
+                Command::BackwardWord => {
+                    self.position =
+                        unicode::prev_word(self.buffer, self.position, |c| self.is_word_char(c));
                     self.refresh_line()?;
                 }
-                keys::CTRL_H | keys::BACKSPACE => {
-                    if self.position == 0 || self.buffer.len() == 0 {
-                        continue;
-                    }
-                    self.position -= 1;
-                    self.buffer.remove(self.position);
+                Command::ForwardWord => {
+                    self.position =
+                        unicode::next_word(self.buffer, self.position, |c| self.is_word_char(c));
                     self.refresh_line()?;
                 }
-                // Enter
-                keys::CTRL_J | keys::CTRL_M => {
+                Command::BackwardDeleteChar => {
+                    if self.position > 0 {
+                        let start = unicode::prev_grapheme(self.buffer, self.position);
+                        self.buffer.drain(start..self.position);
+                        self.position = start;
+                        self.refresh_line()?;
+                    }
+                }
+                Command::AcceptLine => {
                     break;
                 }
-                keys::CTRL_K => {
-                    self.buffer.truncate(self.position);
+                Command::KillLine => {
+                    self.kill(self.position, self.buffer.len(), killed);
+                    self.refresh_line()?;
+                }
+                Command::UnixLineDiscard => {
+                    self.kill(0, self.position, killed);
+                    self.refresh_line()?;
+                }
+                Command::UnixWordRubout => {
+                    let start =
+                        unicode::prev_word(self.buffer, self.position, |c| !c.is_whitespace());
+                    self.kill(start, self.position, killed);
+                    self.refresh_line()?;
+                }
+                Command::KillWord => {
+                    let end =
+                        unicode::next_word(self.buffer, self.position, |c| self.is_word_char(c));
+                    self.kill(self.position, end, killed);
                     self.refresh_line()?;
                 }
-                keys::CTRL_L => {
-                    let mut stdout = io::stdout();
-                    stdout.write_all(b"\x1b[H\x1b[2J")?;
+                Command::BackwardKillWord => {
+                    let start =
+                        unicode::prev_word(self.buffer, self.position, |c| self.is_word_char(c));
+                    self.kill(start, self.position, killed);
                     self.refresh_line()?;
                 }
-                // esc,
-                keys::ESC => {
+                Command::Yank => {
+                    if let Some(text) = self.kill_ring.yank() {
+                        let text = text.clone();
+                        self.yank(&text);
+                        self.refresh_line()?;
+                    }
+                }
+                Command::YankPop => {
+                    if let Some(range) = yanked {
+                        if let Some(text) = self.kill_ring.yank_pop() {
+                            let text = text.clone();
+                            self.buffer.drain(range.clone());
+                            self.position = range.start;
+                            self.yank(&text);
+                            self.refresh_line()?;
+                        }
+                    }
+                }
+                Command::ClearScreen => {
+                    if !self.dumb {
+                        let mut stdout = io::stdout();
+                        stdout.write_all(b"\x1b[H\x1b[2J")?;
+                    }
+                    self.refresh_line()?;
+                }
+                Command::PreviousHistory => {
+                    if let Some(cmd) = self.history.prev() {
+                        let cmd = cmd.clone();
+                        if !used {
+                            tmp.extend(&self.buffer[..]);
+                            used = true;
+                        }
+                        self.replace_buffer(&cmd);
+                        self.refresh_line()?;
+                    }
+                }
+                Command::NextHistory => match self.history.next() {
+                    Some(cmd) => {
+                        let cmd = cmd.clone();
+                        self.replace_buffer(&cmd);
+                        self.refresh_line()?;
+                    }
+                    None => {
+                        if used {
+                            used = false;
+                            self.replace_buffer(&tmp);
+                            tmp.clear();
+                            self.refresh_line()?;
+                        }
+                    }
+                },
+                Command::Complete => match self.completion_mode {
+                    CompletionMode::Cycle => next = self.completion()?,
+                    CompletionMode::Menu if !self.dumb => next = self.completion_menu()?,
+                    CompletionMode::List | CompletionMode::Menu => {
+                        self.complete_prefix(completed)?;
+                        self.completed = true;
+                    }
+                },
+                Command::PossibleCompletions => {
+                    self.possible_completions()?;
+                }
+                Command::HistorySearchBackward => {
+                    let prefix = self.buffer[..self.position].to_vec();
+                    if let Some(cmd) = self.history.prev_with_prefix(&prefix) {
+                        let cmd = cmd.clone();
+                        if !used {
+                            tmp.extend(&self.buffer[..]);
+                            used = true;
+                        }
+                        self.replace_buffer(&cmd);
+                        self.position = unicode::floor_char_boundary(self.buffer, prefix.len());
+                        self.refresh_line()?;
+                    }
+                }
+                Command::HistorySearchForward => {
+                    let prefix = self.buffer[..self.position].to_vec();
+                    let cmd = match self.history.next_with_prefix(&prefix) {
+                        Some(cmd) => Some(cmd.clone()),
+                        None if used => {
+                            used = false;
+                            Some(mem::take(&mut tmp))
+                        }
+                        None => None,
+                    };
+                    if let Some(cmd) = cmd {
+                        self.replace_buffer(&cmd);
+                        self.position = unicode::floor_char_boundary(self.buffer, prefix.len());
+                        self.refresh_line()?;
+                    }
+                }
+                Command::ReverseSearchHistory => {
+                    next = self.search(true)?;
+                }
+                Command::ForwardSearchHistory => {
+                    next = self.search(false)?;
+                }
+                Command::ToggleOverwrite => {
+                    *self.overwrite = !*self.overwrite;
+                }
+                Command::Undo => {
+                    if let Some((buffer, position)) = self.undo.pop() {
+                        self.redo.push(snapshot);
+                        self.replace_buffer(&buffer);
+                        self.position = position;
+                        self.inserting = false;
+                        self.refresh_line()?;
+                    }
                     continue;
                 }
-                _ => {
-                    if self.position < self.buffer.len() {
-                        self.buffer[self.position] = buf[0];
-                    } else {
-                        self.buffer.extend(&buf);
+                Command::Redo => {
+                    if let Some((buffer, position)) = self.redo.pop() {
+                        self.undo.push(snapshot);
+                        self.replace_buffer(&buffer);
+                        self.position = position;
+                        self.inserting = false;
+                        self.refresh_line()?;
                     }
-                    self.position += 1;
+                    continue;
+                }
+                Command::SelfInsert => {
+                    if let Key::Char(c) = key.key {
+                        let mut ch = [0; 4];
+                        let ch = c.encode_utf8(&mut ch).as_bytes();
+                        let end = if *self.overwrite {
+                            unicode::next_grapheme(self.buffer, self.position)
+                        } else {
+                            self.position
+                        };
+                        self.buffer.splice(self.position..end, ch.iter().cloned());
+                        self.position += ch.len();
+                        inserting = true;
+                        self.refresh_line()?;
+                    }
+                }
+                Command::Abort | Command::Noop => {}
+                Command::Custom(f) => {
+                    f(&mut Editor::new(self.buffer, &mut self.position));
                     self.refresh_line()?;
                 }
             }
+
+            if snapshot.0 != *self.buffer {
+                self.save_undo(snapshot, inserting);
+            }
+            self.inserting = inserting;
+        }
+        self.new_line(b"")?;
+        Ok(())
+    }
+
+    /// Write `suffix` at the end of line and move the cursor to the next line.
+    fn new_line(&mut self, suffix: &[u8]) -> io::Result<()> {
+        // Move the cursor below the last row of the wrapped line.
+        if self.position < self.buffer.len() {
+            self.position = self.buffer.len();
+            self.refresh_line()?;
         }
         let mut stdout = io::stdout();
         stdout
-            .write_all(format!("\n\x1b[{}D", self.prompt.len() + self.position).as_bytes())
+            .write_all(&[suffix, b"\r\n"].concat())
             .and(stdout.flush())
     }
 }
@@ -572,25 +997,50 @@ impl<'a> Drop for Line<'a> {
 /// A instance of interaction.
 pub struct Interaction {
     prompt: Vec<u8>,
-    completion: Option<Completion>,
+    completer: Option<Box<dyn Completer + Send>>,
     /// If true, the interaction mode is multi line.
     pub multi: bool,
+    /// If true, a typed char overwrites the char at the cursor instead of being inserted.
+    /// The insert key toggles it.
+    pub overwrite: bool,
     history: History,
+    escape_timeout: Duration,
+    keymap: Keymap,
+    kill_ring: KillRing,
+    word_chars: Vec<char>,
+    completion_mode: CompletionMode,
+    query_items: usize,
+    // If true, the references to the history like `!!` are expanded.
+    expansion: bool,
+    // If true, the expanded line is edited again instead of being returned.
+    verify: bool,
+    // The last error to write the history file, which is not returned by `line`.
+    history_error: Option<io::Error>,
+    // The index of the entry in the history which is appended by the last line.
+    last_entry: Option<usize>,
 }
 
 impl Interaction {
     /// Initialize a interaction.
-    pub fn new(
-        prompt: Vec<u8>,
-        completion: Option<Completion>,
-        multi: bool,
-        limit: usize,
-    ) -> Self {
+    pub fn new(prompt: Vec<u8>, completion: Option<Completion>, multi: bool, limit: usize) -> Self {
         Interaction {
             prompt,
-            completion,
+            completer: completion.map(|completion| {
+                Box::new(CompletionCompleter(completion)) as Box<dyn Completer + Send>
+            }),
             multi,
+            overwrite: false,
             history: History::new(limit),
+            escape_timeout: DEFAULT_ESCAPE_TIMEOUT,
+            keymap: Keymap::default(),
+            kill_ring: KillRing::default(),
+            word_chars: vec![],
+            completion_mode: CompletionMode::Cycle,
+            query_items: DEFAULT_COMPLETION_QUERY_ITEMS,
+            expansion: false,
+            verify: false,
+            history_error: None,
+            last_entry: None,
         }
     }
 
@@ -600,27 +1050,87 @@ impl Interaction {
     }
 
     /// Initialize interaction from prompt.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(prompt: &str) -> Self {
         Interaction::new(prompt.as_bytes().to_vec(), None, true, 0)
     }
 
     /// Get the line of input.
-    pub fn line(&mut self) -> io::Result<Vec<u8>> {
+    ///
+    /// Return [ReadlineError::Interrupted](enum.ReadlineError.html) if the line is canceled
+    /// with Ctrl-C, and [ReadlineError::Eof](enum.ReadlineError.html) if Ctrl-D is pressed
+    /// on an empty line.
+    ///
+    /// If stdin is not a terminal, e.g. a pipe or a file, the line is read as it is without
+    /// line editing.
+    ///
+    /// If the previous line failed to be written to the history file, return the error as
+    /// [ReadlineError::Io](enum.ReadlineError.html) before reading a line.
+    pub fn line(&mut self) -> Result<Vec<u8>, ReadlineError> {
+        if let Some(e) = self.history_error.take() {
+            return Err(ReadlineError::Io(e));
+        }
+        self.last_entry = None;
+        let tty = is_tty(get_stdin_fd());
         let mut buffer = vec![0; 0];
-        Line::new(
-            &mut buffer,
-            &self.prompt,
-            &self.completion,
-            self.multi,
-            &mut self.history,
-        )
-        .fetch()
-        .and_then(|_| {
-            if buffer.len() > 0 {
-                self.history.append(buffer.clone());
+        loop {
+            if tty {
+                Line::new(&mut buffer, self).fetch()?;
+            } else {
+                Interaction::read_line(&mut buffer)?;
             }
-            Ok(buffer)
-        })
+            if !self.expansion {
+                break;
+            }
+            match expansion::expand(&self.history, &buffer) {
+                Ok(Some(expanded)) => {
+                    buffer = expanded;
+                    // Edit the expanded line again to confirm it.
+                    if !(tty && self.verify) {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(message) => {
+                    // Keep the line in the history to fix it.
+                    self.append_history_entry(&buffer);
+                    return Err(ReadlineError::Expansion(message));
+                }
+            }
+        }
+        self.append_history_entry(&buffer);
+        Ok(buffer)
+    }
+
+    /// Append `line` to the history with the current directory.
+    fn append_history_entry(&mut self, line: &[u8]) {
+        if !line.is_empty() {
+            let mut entry = Entry::new(line.to_vec());
+            entry.cwd = env::current_dir().ok();
+            if self.history.append_entry(entry) {
+                self.last_entry = Some(self.history.len() - 1);
+            }
+        }
+        // The line is returned even if it fails to be written.
+        if let Err(e) = self.history.sync() {
+            self.history_error = Some(e);
+        }
+    }
+
+    /// Read a line from stdin which is not a terminal.
+    fn read_line(buffer: &mut Vec<u8>) -> Result<(), ReadlineError> {
+        let mut line = vec![0; 0];
+        if io::stdin().lock().read_until(b'\n', &mut line)? == 0 {
+            return Err(ReadlineError::Eof);
+        }
+        if line.ends_with(b"\n") {
+            line.pop();
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+        }
+        buffer.extend(String::from_utf8_lossy(&line).as_bytes());
+        Ok(())
     }
 
     /// Set the prompt.
@@ -630,12 +1140,105 @@ impl Interaction {
 
     /// Set the completion.
     pub fn set_completion(&mut self, completion: Completion) {
-        self.completion = Some(completion);
+        self.completer = Some(Box::new(CompletionCompleter(completion)));
+    }
+
+    /// Set the completer, which completes the line at the cursor.
+    pub fn set_completer<C: Completer + Send + 'static>(&mut self, completer: C) {
+        self.completer = Some(Box::new(completer));
+    }
+
+    /// Set the behavior of the completion key.
+    pub fn set_completion_mode(&mut self, mode: CompletionMode) {
+        self.completion_mode = mode;
+    }
+
+    /// Set the number of completions to ask whether to list them. If zero, never ask.
+    pub fn set_completion_query_items(&mut self, items: usize) {
+        self.query_items = items;
+    }
+
+    /// Bind `key` to `command`.
+    pub fn bind<K: Into<KeyEvent>>(&mut self, key: K, command: Command) {
+        self.keymap.bind(&[key.into()], command);
+    }
+
+    /// Bind the sequence of `keys` to `command`, e.g. Ctrl-X Ctrl-U.
+    pub fn bind_sequence(&mut self, keys: &[KeyEvent], command: Command) {
+        self.keymap.bind(keys, command);
+    }
+
+    /// Remove the binding of `key`. A char without modifiers is still inserted.
+    pub fn unbind<K: Into<KeyEvent>>(&mut self, key: K) {
+        self.keymap.unbind(&[key.into()]);
+    }
+
+    /// Remove the binding of the sequence of `keys`.
+    pub fn unbind_sequence(&mut self, keys: &[KeyEvent]) {
+        self.keymap.unbind(keys);
+    }
+
+    /// Set the chars which are parts of words in addition to alphanumeric chars.
+    pub fn set_word_chars(&mut self, chars: &str) {
+        self.word_chars = chars.chars().collect();
+    }
+
+    /// Set the time to wait for the rest of an escape sequence after the escape key.
+    pub fn set_escape_timeout(&mut self, timeout: Duration) {
+        self.escape_timeout = timeout;
+    }
+
+    /// If `expansion` is true, the references to the history in the line are expanded like
+    /// bash, e.g. `!!`, `!$`, `!42`, `!git` and `^old^new`.
+    pub fn set_history_expansion(&mut self, expansion: bool) {
+        self.expansion = expansion;
+    }
+
+    /// If `verify` is true, the line expanded from the history is shown to be edited again
+    /// instead of being returned.
+    pub fn set_history_verify(&mut self, verify: bool) {
+        self.verify = verify;
+    }
+
+    /// Return the history.
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Return the mutable history, e.g. to remove an entry.
+    pub fn history_mut(&mut self) -> &mut History {
+        &mut self.history
     }
 
     /// Set the maximum size of history.
     pub fn set_history_limit(&mut self, limit: usize) {
-        self.history = History::new(limit);
+        self.history.set_limit(limit);
+    }
+
+    /// Set the exit status of the command of the last line. Nothing is done if the line is not
+    /// appended to the history, e.g. an empty line or a line ignored by the policies.
+    ///
+    /// If the history file is set by [set_history_file](#method.set_history_file), the status
+    /// is written to it too.
+    pub fn set_exit_status(&mut self, status: i32) -> io::Result<()> {
+        match self.last_entry {
+            Some(index) => self.history.update(index, |e| e.exit_status = Some(status)),
+            None => Ok(()),
+        }
+    }
+
+    /// Set the metadata of the command of the last line, e.g. the duration to run it. Nothing
+    /// is done if the line is not appended to the history.
+    ///
+    /// If the history file is set by [set_history_file](#method.set_history_file), the
+    /// metadata is written to it too.
+    pub fn set_metadata(&mut self, key: &str, value: &str) -> io::Result<()> {
+        match self.last_entry {
+            Some(index) => self.history.update(index, |e| {
+                e.metadata.insert(key.to_string(), value.to_string());
+            }),
+            None => Ok(()),
+        }
     }
 
     /// Load a history from `file_path`.
@@ -647,6 +1250,31 @@ impl Interaction {
     pub fn save_history<P: AsRef<Path>>(&mut self, file_path: P) -> io::Result<()> {
         self.history.save(file_path)
     }
+
+    /// Set the history file which each line is appended to as it is accepted. The history in
+    /// the file is merged.
+    pub fn set_history_file<P: AsRef<Path>>(&mut self, file_path: P) -> io::Result<()> {
+        self.history.set_file(file_path)
+    }
+
+    /// Append the lines which are not saved yet to `file_path`.
+    pub fn append_history<P: AsRef<Path>>(&mut self, file_path: P) -> io::Result<()> {
+        self.history.append_to(file_path)
+    }
+
+    /// Return the last error to write the history file set by
+    /// [set_history_file](#method.set_history_file), and clear it.
+    ///
+    /// The line is returned by [line](#method.line) even if it fails to be written, and the
+    /// error is returned by the next [line](#method.line) unless it is taken here.
+    pub fn take_history_error(&mut self) -> Option<io::Error> {
+        self.history_error.take()
+    }
+
+    /// Merge the history in `file_path`, which may be appended by other sessions.
+    pub fn merge_history<P: AsRef<Path>>(&mut self, file_path: P) -> io::Result<()> {
+        self.history.merge(file_path)
+    }
 }
 
 /// Builder of [Interaction](struct.Interaction.html).
@@ -669,9 +1297,17 @@ impl Interaction {
 /// ```
 pub struct InteractionBuilder {
     prompt: Vec<u8>,
-    completion: Option<Completion>,
+    completer: Option<Box<dyn Completer + Send>>,
     multi: bool,
+    overwrite: bool,
     history: History,
+    escape_timeout: Duration,
+    keymap: Keymap,
+    word_chars: Vec<char>,
+    completion_mode: CompletionMode,
+    query_items: usize,
+    expansion: bool,
+    verify: bool,
 }
 
 impl InteractionBuilder {
@@ -679,9 +1315,17 @@ impl InteractionBuilder {
     pub fn new() -> Self {
         InteractionBuilder {
             prompt: vec![0; 0],
-            completion: None,
+            completer: None,
             multi: true,
+            overwrite: false,
             history: History::new(0),
+            escape_timeout: DEFAULT_ESCAPE_TIMEOUT,
+            keymap: Keymap::default(),
+            word_chars: vec![],
+            completion_mode: CompletionMode::Cycle,
+            query_items: DEFAULT_COMPLETION_QUERY_ITEMS,
+            expansion: false,
+            verify: false,
         }
     }
 
@@ -689,9 +1333,20 @@ impl InteractionBuilder {
     pub fn build(self) -> Interaction {
         Interaction {
             prompt: self.prompt,
-            completion: self.completion,
+            completer: self.completer,
             multi: self.multi,
+            overwrite: self.overwrite,
             history: self.history,
+            escape_timeout: self.escape_timeout,
+            keymap: self.keymap,
+            kill_ring: KillRing::default(),
+            word_chars: self.word_chars,
+            completion_mode: self.completion_mode,
+            query_items: self.query_items,
+            expansion: self.expansion,
+            verify: self.verify,
+            history_error: None,
+            last_entry: None,
         }
     }
 
@@ -709,7 +1364,28 @@ impl InteractionBuilder {
 
     /// Set a completion.
     pub fn completion(mut self, completion: Completion) -> Self {
-        self.completion = Some(completion);
+        self.completer = Some(Box::new(CompletionCompleter(completion)));
+        self
+    }
+
+    /// Set a completer, which completes the line at the cursor, e.g. a closure which captures
+    /// the state. See [Completer](trait.Completer.html).
+    pub fn completer<C: Completer + Send + 'static>(mut self, completer: C) -> Self {
+        self.completer = Some(Box::new(completer));
+        self
+    }
+
+    /// Set a behavior of the completion key. By default,
+    /// [CompletionMode::Cycle](enum.CompletionMode.html).
+    pub fn completion_mode(mut self, mode: CompletionMode) -> Self {
+        self.completion_mode = mode;
+        self
+    }
+
+    /// Set a number of completions to ask whether to list them. If zero, never ask.
+    /// By default, 100.
+    pub fn completion_query_items(mut self, items: usize) -> Self {
+        self.query_items = items;
         self
     }
 
@@ -719,9 +1395,129 @@ impl InteractionBuilder {
         self
     }
 
+    /// Set an overwrite mode. By default, typed chars are inserted.
+    pub fn overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Set a time to wait for the rest of an escape sequence after the escape key.
+    /// If no byte arrives in time, the escape key is handled by itself. By default, 100ms.
+    pub fn escape_timeout(mut self, timeout: Duration) -> Self {
+        self.escape_timeout = timeout;
+        self
+    }
+
+    /// Bind `key` to `command`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use interaction::{Command, InteractionBuilder, KeyEvent};
+    ///
+    /// let inter = InteractionBuilder::new()
+    ///     .bind(KeyEvent::ctrl('p'), Command::PreviousHistory)
+    ///     .bind(
+    ///         KeyEvent::alt('u'),
+    ///         Command::custom(|editor| {
+    ///             let line = editor.buffer().to_ascii_uppercase();
+    ///             editor.set_buffer(&line);
+    ///         }),
+    ///     )
+    ///     .build();
+    /// ```
+    pub fn bind<K: Into<KeyEvent>>(mut self, key: K, command: Command) -> Self {
+        self.keymap.bind(&[key.into()], command);
+        self
+    }
+
+    /// Bind the sequence of `keys` to `command`, e.g. Ctrl-X Ctrl-U.
+    pub fn bind_sequence(mut self, keys: &[KeyEvent], command: Command) -> Self {
+        self.keymap.bind(keys, command);
+        self
+    }
+
+    /// Remove the binding of `key`. A char without modifiers is still inserted.
+    pub fn unbind<K: Into<KeyEvent>>(mut self, key: K) -> Self {
+        self.keymap.unbind(&[key.into()]);
+        self
+    }
+
+    /// Remove the binding of the sequence of `keys`.
+    pub fn unbind_sequence(mut self, keys: &[KeyEvent]) -> Self {
+        self.keymap.unbind(keys);
+        self
+    }
+
+    /// Set chars which are parts of words in addition to alphanumeric chars, e.g. `"-."`.
+    /// They are used by word motions and kills.
+    pub fn word_chars(mut self, chars: &str) -> Self {
+        self.word_chars = chars.chars().collect();
+        self
+    }
+
+    /// Set a history navigation by prefix. If true, Up and Down recall only the commands
+    /// which start with the text before the cursor, like PageUp and PageDown.
+    pub fn history_prefix_search(mut self, enable: bool) -> Self {
+        if enable {
+            self.keymap
+                .bind(&[Key::Up.into()], Command::HistorySearchBackward);
+            self.keymap
+                .bind(&[Key::Down.into()], Command::HistorySearchForward);
+        } else {
+            self.keymap
+                .bind(&[Key::Up.into()], Command::PreviousHistory);
+            self.keymap.bind(&[Key::Down.into()], Command::NextHistory);
+        }
+        self
+    }
+
     /// Set a maximum size of history.
     pub fn history_limit(mut self, limit: usize) -> Self {
-        self.history = History::new(limit);
+        self.history.set_limit(limit);
+        self
+    }
+
+    /// Set a policy for duplicated commands in the history.
+    pub fn history_duplicates(mut self, duplicates: Duplicates) -> Self {
+        self.history.set_duplicates(duplicates);
+        self
+    }
+
+    /// If `ignore_space` is true, a line which starts with a space is not appended to the history.
+    pub fn history_ignore_space(mut self, ignore_space: bool) -> Self {
+        self.history.set_ignore_space(ignore_space);
+        self
+    }
+
+    /// Set a callback to reject lines from the history. A line is not appended if `ignore`
+    /// returns true.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use interaction::{Duplicates, InteractionBuilder};
+    ///
+    /// let inter = InteractionBuilder::new()
+    ///     .history_duplicates(Duplicates::EraseAll)
+    ///     .history_ignore_space(true)
+    ///     .history_ignore(|line| line.windows(8).any(|w| w == b"password"))
+    ///     .build();
+    /// ```
+    pub fn history_ignore<F: Fn(&[u8]) -> bool + Send + 'static>(mut self, ignore: F) -> Self {
+        self.history.set_ignore(ignore);
+        self
+    }
+
+    /// Set a history expansion. If true, the references to the history in the line are
+    /// expanded like bash, e.g. `!!`, `!$`, `!42`, `!git` and `^old^new`.
+    pub fn history_expansion(mut self, expansion: bool) -> Self {
+        self.expansion = expansion;
+        self
+    }
+
+    /// Set a verification of the history expansion. If true, the expanded line is shown to be
+    /// edited again instead of being returned.
+    pub fn history_verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
         self
     }
 
@@ -729,4 +1525,17 @@ impl InteractionBuilder {
     pub fn load_history<P: AsRef<Path>>(mut self, file_path: P) -> io::Result<Self> {
         self.history.load(file_path).and(Ok(self))
     }
+
+    /// Set the history file which each line is appended to as it is accepted, so several
+    /// sessions can share it. The history in the file is merged. See
+    /// [Interaction::set_history_file](struct.Interaction.html#method.set_history_file).
+    pub fn history_file<P: AsRef<Path>>(mut self, file_path: P) -> io::Result<Self> {
+        self.history.set_file(file_path).and(Ok(self))
+    }
+}
+
+impl Default for InteractionBuilder {
+    fn default() -> Self {
+        InteractionBuilder::new()
+    }
 }
@@ -0,0 +1,141 @@
+use std::str;
+use unicode_segmentation::{GraphemeCursor, UnicodeSegmentation};
+use unicode_width::UnicodeWidthStr;
+
+/// Return the length of a UTF-8 sequence from the leading byte.
+/// If the byte can not lead a sequence, return 1.
+pub(crate) fn utf8_len(lead: u8) -> usize {
+    match lead {
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => 1,
+    }
+}
+
+/// Return the start of the grapheme cluster before `position`.
+pub(crate) fn prev_grapheme(buffer: &[u8], position: usize) -> usize {
+    if position == 0 {
+        return 0;
+    }
+    match str::from_utf8(buffer) {
+        Ok(s) if s.is_char_boundary(position) => {
+            let mut cursor = GraphemeCursor::new(position, s.len(), true);
+            match cursor.prev_boundary(s, 0) {
+                Ok(Some(n)) => n,
+                _ => position - 1,
+            }
+        }
+        _ => position - 1,
+    }
+}
+
+/// Return the end of the grapheme cluster at `position`.
+pub(crate) fn next_grapheme(buffer: &[u8], position: usize) -> usize {
+    if position >= buffer.len() {
+        return buffer.len();
+    }
+    match str::from_utf8(buffer) {
+        Ok(s) if s.is_char_boundary(position) => {
+            let mut cursor = GraphemeCursor::new(position, s.len(), true);
+            match cursor.next_boundary(s, 0) {
+                Ok(Some(n)) => n,
+                _ => position + 1,
+            }
+        }
+        _ => position + 1,
+    }
+}
+
+/// Return the number of columns to display a grapheme cluster.
+/// A cluster never takes more than 2 columns, e.g. an emoji sequence.
+pub(crate) fn grapheme_width(grapheme: &str) -> usize {
+    grapheme.width().min(2)
+}
+
+/// Return the number of columns to display `s`.
+pub(crate) fn width(s: &[u8]) -> usize {
+    String::from_utf8_lossy(s)
+        .graphemes(true)
+        .map(grapheme_width)
+        .sum()
+}
+
+/// Return the longest prefix of `s` which is displayed within `width` columns.
+pub(crate) fn truncate(s: &[u8], width: usize) -> Vec<u8> {
+    let s = String::from_utf8_lossy(s);
+    let mut cnt = 0;
+    let end = s
+        .grapheme_indices(true)
+        .find(|(_, g)| {
+            cnt += grapheme_width(g);
+            cnt > width
+        })
+        .map_or(s.len(), |(i, _)| i);
+    s[..end].as_bytes().to_vec()
+}
+
+/// Return grapheme clusters of `s` with the byte offset of each cluster.
+pub(crate) fn graphemes(s: &str) -> impl Iterator<Item = (usize, &str)> {
+    s.grapheme_indices(true)
+}
+
+/// Return the largest char boundary of `buffer` which is not greater than `position`.
+pub(crate) fn floor_char_boundary(buffer: &[u8], position: usize) -> usize {
+    let mut position = position.min(buffer.len());
+    while position > 0 && position < buffer.len() && buffer[position] & 0xc0 == 0x80 {
+        position -= 1;
+    }
+    position
+}
+
+/// Return true if the grapheme cluster at `position` is a part of a word.
+fn is_word<F: Fn(char) -> bool>(buffer: &[u8], position: usize, is_word_char: &F) -> bool {
+    let end = next_grapheme(buffer, position);
+    match String::from_utf8_lossy(&buffer[position..end])
+        .chars()
+        .next()
+    {
+        Some(c) => is_word_char(c),
+        None => false,
+    }
+}
+
+/// Return the start of the word before `position`.
+pub(crate) fn prev_word<F: Fn(char) -> bool>(
+    buffer: &[u8],
+    position: usize,
+    is_word_char: F,
+) -> usize {
+    let mut position = position;
+    let mut in_word = false;
+    while position > 0 {
+        let prev = prev_grapheme(buffer, position);
+        if is_word(buffer, prev, &is_word_char) {
+            in_word = true;
+        } else if in_word {
+            break;
+        }
+        position = prev;
+    }
+    position
+}
+
+/// Return the end of the word after `position`.
+pub(crate) fn next_word<F: Fn(char) -> bool>(
+    buffer: &[u8],
+    position: usize,
+    is_word_char: F,
+) -> usize {
+    let mut position = position;
+    let mut in_word = false;
+    while position < buffer.len() {
+        if is_word(buffer, position, &is_word_char) {
+            in_word = true;
+        } else if in_word {
+            break;
+        }
+        position = next_grapheme(buffer, position);
+    }
+    position
+}